@@ -0,0 +1,369 @@
+use crate::framebuffer::TextureData;
+use sdl2::rect::Rect;
+use std::f64::consts::PI;
+use std::io::ErrorKind;
+use std::path::Path;
+
+// How tall sprites are in the level by default (walls are 1 tall)
+const DEFAULT_HEIGHT: f64 = 1.0 / 1.6;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum AnimationKind {
+    Idle,
+    Walk,
+    Attack,
+    Death,
+}
+
+impl AnimationKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "idle" => Some(Self::Idle),
+            "walk" => Some(Self::Walk),
+            "attack" => Some(Self::Attack),
+            "death" => Some(Self::Death),
+            _ => None,
+        }
+    }
+}
+
+// A sequence of frames next to each other on a row of a sprite sheet,
+// sprites with 8 directions have 8 rows (one for each direction)
+// starting at `row`
+struct Animation {
+    kind: AnimationKind,
+    row: u32,
+    frames: u32,
+    // Seconds that each frame is shown for
+    frame_time: f64,
+    // Animations that don't loop stay on their last frame
+    looping: bool,
+}
+
+// Describes how a sprite's image is split up into frames
+pub struct SpriteDef {
+    frame_width: u32,
+    frame_height: u32,
+    // Sprites with directions have a frame for every 45 degrees
+    // that they can be looked at from, the first row is the front
+    // and the rest go around the sprite the same way the camera
+    // turns when turning right
+    directional: bool,
+    // Height of the sprite in the level, the width comes from the frame size
+    pub height: f64,
+    animations: Vec<Animation>,
+}
+
+// The animation that a sprite is playing and how long it has been playing for
+#[derive(Clone)]
+pub struct AnimationState {
+    pub kind: AnimationKind,
+    time: f64,
+}
+
+impl AnimationState {
+    pub fn new() -> Self {
+        Self {
+            kind: AnimationKind::Idle,
+            time: 0.0,
+        }
+    }
+
+    // Switches to another animation, starting it from the beginning
+    pub fn play(&mut self, kind: AnimationKind) {
+        if self.kind != kind {
+            self.kind = kind;
+            self.time = 0.0;
+        }
+    }
+
+    pub fn update(&mut self, dt: f64) {
+        self.time += dt;
+    }
+}
+
+impl SpriteDef {
+    // A sprite that is just the whole image
+    pub fn single_frame(image: &TextureData) -> Self {
+        Self {
+            frame_width: image.width(),
+            frame_height: image.height(),
+            directional: false,
+            height: DEFAULT_HEIGHT,
+            animations: vec![],
+        }
+    }
+
+    // Loads the sprite sheet description for an image, it is a file next to
+    // the image with the extension changed to `.sheet` and looks like:
+    //
+    // frame 16 16
+    // directions 8
+    // height 0.625
+    // # name, first row, number of frames, seconds per frame, loop or once
+    // animation idle 0 1 1.0 loop
+    // animation walk 8 4 0.15 loop
+    // animation death 16 4 0.1 once
+    //
+    // Every line is optional, images without a sheet file are a single frame
+    pub fn load(image_path: &str, image: &TextureData) -> Self {
+        let mut def = Self::single_frame(image);
+
+        let path = Path::new(image_path).with_extension("sheet");
+        let path = path.to_string_lossy();
+        let text = match std::fs::read_to_string(path.as_ref()) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return def,
+            Err(e) => {
+                eprintln!("Failed to read {path}: {e}");
+                return def;
+            }
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Err(msg) = def.parse_line(line) {
+                eprintln!("{path}:{}: {msg}", i + 1);
+            }
+        }
+
+        def
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |word: &str| -> Result<f64, String> {
+            word.parse::<f64>()
+                .ok()
+                .filter(|value| value.is_finite() && *value >= 0.0)
+                .ok_or(format!("invalid number `{word}`"))
+        };
+
+        match words.as_slice() {
+            ["frame", w, h] => {
+                let (w, h) = (number(w)? as u32, number(h)? as u32);
+                if w == 0 || h == 0 {
+                    return Err("frames can't be empty".to_owned());
+                }
+                (self.frame_width, self.frame_height) = (w, h);
+            }
+            ["directions", "1"] => self.directional = false,
+            ["directions", "8"] => self.directional = true,
+            ["directions", _] => return Err("directions has to be 1 or 8".to_owned()),
+            ["height", height] => self.height = number(height)?,
+            ["animation", name, row, frames, frame_time, looping] => {
+                let kind =
+                    AnimationKind::from_name(name).ok_or(format!("unknown animation `{name}`"))?;
+                let frames = number(frames)? as u32;
+                let frame_time = number(frame_time)?;
+                if frames == 0 || frame_time == 0.0 {
+                    return Err("animations need at least one frame and a frame time".to_owned());
+                }
+                let looping = match *looping {
+                    "loop" => true,
+                    "once" => false,
+                    _ => return Err(format!("expected loop or once, found `{looping}`")),
+                };
+
+                self.animations.retain(|animation| animation.kind != kind);
+                self.animations.push(Animation {
+                    kind,
+                    row: number(row)? as u32,
+                    frames,
+                    frame_time,
+                    looping,
+                });
+            }
+            _ => return Err(format!("unknown line `{line}`")),
+        }
+
+        Ok(())
+    }
+
+    pub fn has_animation(&self, kind: AnimationKind) -> bool {
+        self.animations
+            .iter()
+            .any(|animation| animation.kind == kind)
+    }
+
+    // Sprites without the animation they are playing show their idle animation
+    fn animation(&self, kind: AnimationKind) -> Option<&Animation> {
+        let find = |kind| self.animations.iter().find(|anim| anim.kind == kind);
+        find(kind).or_else(|| find(AnimationKind::Idle))
+    }
+
+    // Part of the sprite sheet to draw, `view_angle` is the angle from the sprite
+    // to the camera compared to the direction that the sprite is facing
+    pub fn frame_rect(&self, state: &AnimationState, view_angle: f64) -> Rect {
+        let (row, frame) = match self.animation(state.kind) {
+            Some(animation) => {
+                let frame = (state.time / animation.frame_time) as u32;
+                let frame = if animation.looping {
+                    frame % animation.frames
+                } else {
+                    frame.min(animation.frames - 1)
+                };
+                (animation.row, frame)
+            }
+            None => (0, 0),
+        };
+
+        let direction = if self.directional {
+            ((view_angle / (PI / 4.0)).round() as i64).rem_euclid(8) as u32
+        } else {
+            0
+        };
+
+        Rect::new(
+            (frame * self.frame_width) as i32,
+            ((row + direction) * self.frame_height) as i32,
+            self.frame_width,
+            self.frame_height,
+        )
+    }
+
+    // Frame that is shown in the editor and the HUD
+    pub fn icon_rect(&self) -> Rect {
+        self.frame_rect(&AnimationState::new(), 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 16x16 frame sprite set up from the lines of a sheet file
+    fn sheet(lines: &[&str]) -> SpriteDef {
+        let mut def = SpriteDef {
+            frame_width: 16,
+            frame_height: 16,
+            directional: false,
+            height: DEFAULT_HEIGHT,
+            animations: vec![],
+        };
+        for line in lines {
+            def.parse_line(line).unwrap();
+        }
+        def
+    }
+
+    fn playing(kind: AnimationKind, time: f64) -> AnimationState {
+        let mut state = AnimationState::new();
+        state.play(kind);
+        state.update(time);
+        state
+    }
+
+    #[test]
+    fn parses_sheet_lines() {
+        let def = sheet(&[
+            "frame 24 32",
+            "directions 8",
+            "height 0.5",
+            "animation walk 8 4 0.15 loop",
+            "animation death 16 3 0.1 once",
+        ]);
+        assert_eq!((def.frame_width, def.frame_height), (24, 32));
+        assert!(def.directional);
+        assert_eq!(def.height, 0.5);
+        assert!(def.has_animation(AnimationKind::Walk));
+        assert!(def.has_animation(AnimationKind::Death));
+        assert!(!def.has_animation(AnimationKind::Attack));
+
+        // A second line for the same animation replaces the first
+        let def = sheet(&["animation idle 0 1 1 loop", "animation idle 2 1 1 loop"]);
+        assert_eq!(def.animations.len(), 1);
+        assert_eq!(def.animations[0].row, 2);
+    }
+
+    #[test]
+    fn rejects_bad_sheet_lines() {
+        for line in [
+            "frame 0 16",
+            "frame sixteen 16",
+            "frame 16",
+            "directions 4",
+            "height -1",
+            "height inf",
+            "animation fly 0 1 1 loop",
+            "animation walk 0 0 1 loop",
+            "animation walk 0 1 0 loop",
+            "animation walk 0 1 1 forever",
+            "animation walk 0 1 1",
+            "scale 2",
+        ] {
+            assert!(sheet(&[]).parse_line(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn shipped_sheets_are_valid() {
+        for text in [
+            include_str!("../assets/images/sprites/alien1.sheet"),
+            include_str!("../assets/images/sprites/alien2.sheet"),
+        ] {
+            let mut def = sheet(&[]);
+            for line in text.lines().filter(|line| !line.starts_with('#')) {
+                def.parse_line(line).unwrap();
+            }
+            for kind in [
+                AnimationKind::Idle,
+                AnimationKind::Walk,
+                AnimationKind::Attack,
+                AnimationKind::Death,
+            ] {
+                assert!(def.has_animation(kind));
+            }
+        }
+    }
+
+    #[test]
+    fn picks_the_row_for_the_view_angle() {
+        let def = sheet(&["directions 8", "animation idle 0 1 1 loop"]);
+        let idle = AnimationState::new();
+        for direction in 0..8 {
+            let angle = direction as f64 * PI / 4.0;
+            // Angles are rounded to the closest direction
+            for angle in [angle, angle + 0.3, angle - 0.3, angle - 2.0 * PI] {
+                let frame = def.frame_rect(&idle, angle);
+                assert_eq!(frame, Rect::new(0, direction * 16, 16, 16), "{angle}");
+            }
+        }
+
+        // Sprites without directions look the same from everywhere
+        let def = sheet(&["animation idle 2 1 1 loop"]);
+        for direction in 0..8 {
+            let frame = def.frame_rect(&idle, direction as f64 * PI / 4.0);
+            assert_eq!(frame, Rect::new(0, 32, 16, 16));
+        }
+    }
+
+    #[test]
+    fn looping_animations_wrap_and_others_hold_the_last_frame() {
+        let def = sheet(&[
+            "directions 8",
+            "animation walk 8 4 0.25 loop",
+            "animation death 16 4 0.25 once",
+        ]);
+
+        let frame_x = |kind, time| def.frame_rect(&playing(kind, time), 0.0).x();
+        assert_eq!(frame_x(AnimationKind::Walk, 0.1), 0);
+        assert_eq!(frame_x(AnimationKind::Walk, 0.8), 48);
+        assert_eq!(frame_x(AnimationKind::Walk, 1.1), 0);
+        assert_eq!(frame_x(AnimationKind::Death, 0.8), 48);
+        assert_eq!(frame_x(AnimationKind::Death, 10.0), 48);
+
+        // The direction is added to the animation's first row
+        let frame = def.frame_rect(&playing(AnimationKind::Death, 0.3), PI / 2.0);
+        assert_eq!(frame, Rect::new(16, (16 + 2) * 16, 16, 16));
+
+        // Animations that the sprite doesn't have fall back to idle,
+        // or the first frame if there isn't an idle animation either
+        let frame = def.frame_rect(&playing(AnimationKind::Attack, 0.6), 0.0);
+        assert_eq!(frame, Rect::new(0, 0, 16, 16));
+    }
+}
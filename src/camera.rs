@@ -1,4 +1,5 @@
 use crate::{
+    config::Config,
     events::InputState,
     sprite::{new_vector2, Vector2f64},
 };
@@ -7,9 +8,12 @@ use sdl2::keyboard::Scancode;
 pub struct Camera {
     pub position: Vector2f64,
     pub rotation: f64,
-    pub speed: f64,
+    pub velocity: Vector2f64,
     pub rotation_speed: f64,
     pub fov: f64,
+    // Direction the player wants to move in (length 0 or 1)
+    move_dir: Vector2f64,
+    running: bool,
 }
 
 impl Camera {
@@ -18,32 +22,89 @@ impl Camera {
             position: new_vector2(x, y),
             rotation: cam_rotation,
             fov: cam_fov,
-            speed: 0.0,
+            velocity: new_vector2(0.0, 0.0),
             rotation_speed: 0.0,
+            move_dir: new_vector2(0.0, 0.0),
+            running: false,
         }
     }
 
-    pub fn update(&mut self, dt: f64) {
-        self.position.x += self.speed * self.rotation.cos() * dt;
-        self.position.y += self.speed * self.rotation.sin() * dt;
+    // Speeds up towards the direction the player wants to move in (or slows
+    // down if there isn't one) and returns how far the camera wants to move
+    // this frame, the position is left to be updated by the collision code
+    pub fn update(&mut self, dt: f64, config: &Config) -> Vector2f64 {
         self.rotation += self.rotation_speed * dt;
+
+        let moving = self.move_dir.x != 0.0 || self.move_dir.y != 0.0;
+        let max_speed = if self.running {
+            config.run_speed
+        } else {
+            config.walk_speed
+        };
+        let (target, rate) = if moving {
+            (
+                new_vector2(self.move_dir.x * max_speed, self.move_dir.y * max_speed),
+                config.acceleration,
+            )
+        } else {
+            (new_vector2(0.0, 0.0), config.friction)
+        };
+
+        let diff = new_vector2(target.x - self.velocity.x, target.y - self.velocity.y);
+        let diff_len = (diff.x * diff.x + diff.y * diff.y).sqrt();
+        let change = rate * dt;
+        if diff_len <= change {
+            self.velocity = target;
+        } else {
+            self.velocity.x += diff.x / diff_len * change;
+            self.velocity.y += diff.y / diff_len * change;
+        }
+
+        new_vector2(self.velocity.x * dt, self.velocity.y * dt)
     }
 
-    pub fn handle_key_input(&mut self, input: &InputState) {
-        if input.key_is_clicked(Scancode::Up) {
-            self.speed = 2.0;
-        } else if input.key_is_clicked(Scancode::Down) {
-            self.speed = -2.0;
-        } else if !input.key_is_held(Scancode::Up) && !input.key_is_held(Scancode::Down) {
-            self.speed = 0.0;
+    // WASD (or the up and down arrows) to move, left and right
+    // arrows or the mouse to turn and shift to run
+    pub fn handle_input(&mut self, input: &InputState, config: &Config) {
+        let held = |keys: &[Scancode]| keys.iter().any(|key| input.key_is_held(*key));
+
+        let mut forward = 0.0f64;
+        if held(&[Scancode::W, Scancode::Up]) {
+            forward += 1.0;
+        }
+        if held(&[Scancode::S, Scancode::Down]) {
+            forward -= 1.0;
         }
 
-        if input.key_is_clicked(Scancode::Left) {
-            self.rotation_speed = -1.0;
-        } else if input.key_is_clicked(Scancode::Right) {
-            self.rotation_speed = 1.0;
-        } else if !input.key_is_held(Scancode::Left) && !input.key_is_held(Scancode::Right) {
-            self.rotation_speed = 0.0;
+        let mut strafe = 0.0f64;
+        if held(&[Scancode::D]) {
+            strafe += 1.0;
+        }
+        if held(&[Scancode::A]) {
+            strafe -= 1.0;
         }
+
+        // Moving diagonally shouldn't be faster
+        let len = (forward * forward + strafe * strafe).sqrt();
+        let (forward, strafe) = if len > 0.0 {
+            (forward / len, strafe / len)
+        } else {
+            (0.0, 0.0)
+        };
+
+        let (sin, cos) = self.rotation.sin_cos();
+        self.move_dir = new_vector2(forward * cos - strafe * sin, forward * sin + strafe * cos);
+        self.running = held(&[Scancode::LShift, Scancode::RShift]);
+
+        self.rotation_speed = 0.0;
+        if held(&[Scancode::Left]) {
+            self.rotation_speed -= config.turn_speed;
+        }
+        if held(&[Scancode::Right]) {
+            self.rotation_speed += config.turn_speed;
+        }
+
+        let (mouse_dx, _) = input.mouse_motion();
+        self.rotation += mouse_dx as f64 * config.mouse_sensitivity;
     }
 }
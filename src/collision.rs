@@ -0,0 +1,105 @@
+use crate::level::Level;
+use crate::manifest::Manifest;
+use crate::sprite::{dist, new_vector2, Sprite, Vector2f64};
+
+// How much space solid sprites take up
+const SPRITE_RADIUS: f64 = 0.3;
+// Longest distance moved in one step when checking for collisions
+const MAX_STEP: f64 = 0.05;
+
+// Anything outside of the level counts as a wall so that the player can't leave it
+fn tile_is_solid(level: &Level, manifest: &Manifest, x: isize, y: isize) -> bool {
+    level.out_of_bounds(x, y) || manifest.tile_is_solid(level.get_tile(x, y))
+}
+
+// Returns how far a circle is pushed into the solid tiles around
+// it (the deepest overlap), 0 if it isn't touching any of them
+fn tile_overlap(level: &Level, manifest: &Manifest, pos: &Vector2f64, radius: f64) -> f64 {
+    let (startx, endx) = ((pos.x - radius).floor(), (pos.x + radius).floor());
+    let (starty, endy) = ((pos.y - radius).floor(), (pos.y + radius).floor());
+
+    let mut overlap = 0.0f64;
+    for y in starty as isize..=endy as isize {
+        for x in startx as isize..=endx as isize {
+            if !tile_is_solid(level, manifest, x, y) {
+                continue;
+            }
+
+            // Closest point in the tile (or on its segment) to the center of the circle
+            let segment = manifest
+                .tile(level.get_tile(x, y))
+                .and_then(|tile| tile.segment);
+            let closest = match segment {
+                Some(segment) => segment.closest_point(x, y, pos),
+                None => new_vector2(
+                    pos.x.clamp(x as f64, x as f64 + 1.0),
+                    pos.y.clamp(y as f64, y as f64 + 1.0),
+                ),
+            };
+            overlap = overlap.max(radius - dist(&closest, pos));
+        }
+    }
+
+    overlap
+}
+
+// Returns true if moving from `from` to `to` would push a circle into a solid
+// sprite, moving away from a sprite that is already overlapping is allowed so
+// that nothing can get stuck inside of one
+fn hits_sprite(
+    manifest: &Manifest,
+    sprites: &[Sprite],
+    from: &Vector2f64,
+    to: &Vector2f64,
+    radius: f64,
+) -> bool {
+    sprites
+        .iter()
+        .filter(|sprite| manifest.sprite_properties(sprite.sprite_type).solid)
+        .any(|sprite| {
+            let d = dist(&sprite.pos, to);
+            d < radius + SPRITE_RADIUS && d < dist(&sprite.pos, from)
+        })
+}
+
+// Moving out of a wall that the circle is already stuck in (if it spawned
+// inside of one) is allowed, moving further into a wall isn't
+fn blocked(
+    level: &Level,
+    manifest: &Manifest,
+    from: &Vector2f64,
+    to: &Vector2f64,
+    radius: f64,
+) -> bool {
+    let overlap = tile_overlap(level, manifest, to, radius);
+    (overlap > 0.0 && overlap > tile_overlap(level, manifest, from, radius))
+        || hits_sprite(manifest, &level.sprites, from, to, radius)
+}
+
+// Moves a circle by (dx, dy), each axis is moved separately so that
+// when only one of them is blocked the circle slides along the wall,
+// the manifest says which tiles and sprites are solid
+pub fn move_circle(
+    level: &Level,
+    manifest: &Manifest,
+    pos: &mut Vector2f64,
+    dx: f64,
+    dy: f64,
+    radius: f64,
+) {
+    // Take small steps so that fast movement can't skip over a wall
+    let steps = ((dx.abs().max(dy.abs()) / MAX_STEP).ceil() as usize).max(1);
+    let (step_x, step_y) = (dx / steps as f64, dy / steps as f64);
+
+    for _ in 0..steps {
+        let moved_x = new_vector2(pos.x + step_x, pos.y);
+        if !blocked(level, manifest, pos, &moved_x, radius) {
+            *pos = moved_x;
+        }
+
+        let moved_y = new_vector2(pos.x, pos.y + step_y);
+        if !blocked(level, manifest, pos, &moved_y, radius) {
+            *pos = moved_y;
+        }
+    }
+}
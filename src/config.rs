@@ -0,0 +1,90 @@
+use std::io::ErrorKind;
+
+// Settings are read from this file in the working directory when the game starts
+pub const CONFIG_PATH: &str = "settings.cfg";
+
+// Settings that control how the player moves and how the game is drawn, the config file has
+// one `name = value` per line and lines starting with # are ignored
+#[derive(Clone)]
+pub struct Config {
+    pub walk_speed: f64,
+    // Speed while holding shift
+    pub run_speed: f64,
+    // How quickly the player speeds up and slows down (in units per second squared)
+    pub acceleration: f64,
+    pub friction: f64,
+    // Turning speed with the arrow keys (in radians per second)
+    pub turn_speed: f64,
+    // Radians turned for each pixel the mouse moves
+    pub mouse_sensitivity: f64,
+    // Resolution the game is rendered at compared to the window, lower
+    // values are faster and look more pixelated
+    pub render_scale: f64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            walk_speed: 2.0,
+            run_speed: 4.0,
+            acceleration: 16.0,
+            friction: 12.0,
+            turn_speed: 1.5,
+            mouse_sensitivity: 0.003,
+            render_scale: 1.0,
+        }
+    }
+}
+
+impl Config {
+    // Loads the config file, any setting that is missing or can't be
+    // read keeps its default value
+    pub fn load(path: &str) -> Self {
+        let mut config = Self::default();
+
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return config,
+            Err(e) => {
+                eprintln!("Failed to read {path}: {e}");
+                return config;
+            }
+        };
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => {
+                    eprintln!("{path}:{}: expected `name = value`", i + 1);
+                    continue;
+                }
+            };
+
+            let setting = match name {
+                "walk_speed" => &mut config.walk_speed,
+                "run_speed" => &mut config.run_speed,
+                "acceleration" => &mut config.acceleration,
+                "friction" => &mut config.friction,
+                "turn_speed" => &mut config.turn_speed,
+                "mouse_sensitivity" => &mut config.mouse_sensitivity,
+                "render_scale" => &mut config.render_scale,
+                _ => {
+                    eprintln!("{path}:{}: unknown setting `{name}`", i + 1);
+                    continue;
+                }
+            };
+
+            match value.parse::<f64>() {
+                Ok(value) if value.is_finite() => *setting = value,
+                _ => eprintln!("{path}:{}: invalid value `{value}` for {name}", i + 1),
+            }
+        }
+
+        config
+    }
+}
@@ -0,0 +1,174 @@
+use crate::level::Level;
+use crate::manifest::Manifest;
+use crate::sprite::{dist, new_vector2, Vector2f64};
+use std::collections::HashMap;
+
+// How much of a door slides open per second
+const DOOR_SPEED: f64 = 1.5;
+// How long a door stays open before it tries to close (in seconds)
+const OPEN_TIME: f64 = 3.0;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum DoorState {
+    Closed,
+    Opening,
+    Open,
+    Closing,
+}
+
+// A door sits in the middle of its tile and slides sideways into the
+// wall next to it when it opens
+#[derive(Clone)]
+pub struct Door {
+    // Tile that is put back into the level when the door closes, open doors
+    // are taken out of the level so that things can move through them
+    pub tile: u8,
+    // Doors with walls to the north and south go down the middle of their
+    // tile (facing east and west), the rest go across it
+    vertical: bool,
+    // How far the door has slid open, from 0 (closed) to 1 (open)
+    offset: f64,
+    state: DoorState,
+    // How long the door has been open for
+    open_timer: f64,
+}
+
+impl Door {
+    fn new(tile: u8, vertical: bool) -> Self {
+        Self {
+            tile,
+            vertical,
+            offset: 0.0,
+            state: DoorState::Closed,
+            open_timer: 0.0,
+        }
+    }
+
+    pub fn vertical(&self) -> bool {
+        self.vertical
+    }
+
+    // Where along the door's texture a point on the door is
+    pub fn texture_u(&self, hit: &Vector2f64) -> f64 {
+        let along = if self.vertical { hit.y } else { hit.x };
+        (along.fract() - self.offset).max(0.0)
+    }
+
+    // Returns how far along a ray the part of the door that hasn't
+    // slid into the wall yet is hit, None if the ray misses it
+    pub fn intersect(&self, x: isize, y: isize, start: &Vector2f64, angle: f64) -> Option<f64> {
+        // Swap the axes for doors going across their tile so that
+        // both kinds of doors can be checked the same way
+        let (start_across, start_along, dir_across, dir_along, across, along) = if self.vertical {
+            (start.x, start.y, angle.cos(), angle.sin(), x, y)
+        } else {
+            (start.y, start.x, angle.sin(), angle.cos(), y, x)
+        };
+
+        if dir_across == 0.0 {
+            return None;
+        }
+
+        let d = (across as f64 + 0.5 - start_across) / dir_across;
+        let hit_along = start_along + dir_along * d - along as f64;
+        if d < 0.0 || hit_along < self.offset || hit_along >= 1.0 {
+            return None;
+        }
+
+        Some(d)
+    }
+}
+
+// Returns true if a circle overlaps a tile
+fn overlaps_tile(pos: &Vector2f64, radius: f64, x: isize, y: isize) -> bool {
+    let closest = new_vector2(
+        pos.x.clamp(x as f64, x as f64 + 1.0),
+        pos.y.clamp(y as f64, y as f64 + 1.0),
+    );
+    dist(&closest, pos) < radius
+}
+
+// Every door in the level and how far open it is, this is kept
+// while the level is being played
+#[derive(Clone, Default)]
+pub struct Doors {
+    doors: HashMap<(isize, isize), Door>,
+}
+
+impl Doors {
+    // Finds the tiles in the level that the manifest says are doors
+    pub fn new(level: &Level, manifest: &Manifest) -> Self {
+        let mut doors = HashMap::new();
+        let solid = |x, y| manifest.tile_is_solid(level.get_tile(x, y));
+
+        for y in 0..level.height as isize {
+            for x in 0..level.width as isize {
+                let tile = level.get_tile(x, y);
+                if manifest.tile(tile).is_some_and(|tile| tile.door) {
+                    let vertical = solid(x, y - 1) && solid(x, y + 1);
+                    doors.insert((x, y), Door::new(tile, vertical));
+                }
+            }
+        }
+
+        Self { doors }
+    }
+
+    pub fn get(&self, x: isize, y: isize) -> Option<&Door> {
+        self.doors.get(&(x, y))
+    }
+
+    // Starts opening a door, doors that are closing open back up
+    pub fn open(&mut self, x: isize, y: isize) {
+        if let Some(door) = self.doors.get_mut(&(x, y)) {
+            if matches!(door.state, DoorState::Closed | DoorState::Closing) {
+                door.state = DoorState::Opening;
+            }
+        }
+    }
+
+    // Opens every door with a tile type (for switches)
+    pub fn open_all(&mut self, tile: u8) {
+        for door in self.doors.values_mut() {
+            if door.tile == tile && door.state != DoorState::Open {
+                door.state = DoorState::Opening;
+            }
+        }
+    }
+
+    // Slides the doors open and closed, `occupants` are the circles that
+    // doors wait for to leave before they close so that nothing gets crushed
+    pub fn update(&mut self, level: &mut Level, occupants: &[(Vector2f64, f64)], dt: f64) {
+        for ((x, y), door) in &mut self.doors {
+            match door.state {
+                DoorState::Closed => {}
+                DoorState::Opening => {
+                    door.offset += DOOR_SPEED * dt;
+                    if door.offset >= 1.0 {
+                        door.offset = 1.0;
+                        door.state = DoorState::Open;
+                        door.open_timer = 0.0;
+                        level.set_tile(*x, *y, 0);
+                    }
+                }
+                DoorState::Open => {
+                    door.open_timer += dt;
+                    let blocked = occupants
+                        .iter()
+                        .any(|(pos, radius)| overlaps_tile(pos, *radius, *x, *y));
+                    if door.open_timer >= OPEN_TIME && !blocked {
+                        door.state = DoorState::Closing;
+                        level.set_tile(*x, *y, door.tile);
+                    }
+                }
+                DoorState::Closing => {
+                    door.offset -= DOOR_SPEED * dt;
+                    if door.offset <= 0.0 {
+                        door.offset = 0.0;
+                        door.state = DoorState::Closed;
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use crate::door::Doors;
+use crate::level::Level;
+use crate::manifest::Manifest;
+use crate::pathfinding::find_path;
+use crate::raycast::line_of_sight;
+use crate::sprite::{dist, new_vector2, Vector2f64};
+
+// How far away an enemy can notice the player from
+const SIGHT_RANGE: f64 = 16.0;
+// How often a chasing enemy looks for a new path to the player (in seconds)
+const REPATH_TIME: f64 = 0.5;
+
+// Types of enemies that sprites can be in the manifest
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum EnemyKind {
+    // Fast, attacks up close
+    Alien1,
+    // Slow, attacks from a distance
+    Alien2,
+}
+
+impl EnemyKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "alien1" => Some(Self::Alien1),
+            "alien2" => Some(Self::Alien2),
+            _ => None,
+        }
+    }
+
+    pub fn health(&self) -> f64 {
+        match self {
+            Self::Alien1 => 30.0,
+            Self::Alien2 => 20.0,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum EnemyState {
+    // Waiting until the player comes into view
+    Idle,
+    Chasing,
+    Attacking,
+}
+
+#[derive(Clone)]
+pub struct Enemy {
+    pub state: EnemyState,
+    pub speed: f64,
+    pub attack_range: f64,
+    pub attack_damage: f64,
+    // Time between attacks (in seconds)
+    pub attack_time: f64,
+    attack_timer: f64,
+    path: Vec<(isize, isize)>,
+    repath_timer: f64,
+    // Tile the player was last seen on
+    last_seen: Option<(isize, isize)>,
+}
+
+impl Enemy {
+    pub fn new(move_speed: f64, range: f64, damage: f64, time_between_attacks: f64) -> Self {
+        Self {
+            state: EnemyState::Idle,
+            speed: move_speed,
+            attack_range: range,
+            attack_damage: damage,
+            attack_time: time_between_attacks,
+            attack_timer: 0.0,
+            path: vec![],
+            repath_timer: 0.0,
+            last_seen: None,
+        }
+    }
+
+    pub fn from_kind(kind: EnemyKind) -> Self {
+        match kind {
+            EnemyKind::Alien1 => Self::new(1.5, 1.0, 10.0, 1.0),
+            EnemyKind::Alien2 => Self::new(1.0, 5.0, 5.0, 1.5),
+        }
+    }
+
+    // Moves along the current path, returns true if the end was reached
+    fn follow_path(&mut self, pos: &mut Vector2f64, dt: f64) -> bool {
+        let mut move_dist = self.speed * dt;
+
+        while move_dist > 0.0 {
+            let (tilex, tiley) = match self.path.first() {
+                Some(tile) => *tile,
+                None => return true,
+            };
+
+            // Walk towards the center of the next tile
+            let target = new_vector2(tilex as f64 + 0.5, tiley as f64 + 0.5);
+            let d = dist(pos, &target);
+
+            if d <= move_dist {
+                *pos = target;
+                move_dist -= d;
+                self.path.remove(0);
+            } else {
+                pos.x += (target.x - pos.x) / d * move_dist;
+                pos.y += (target.y - pos.y) / d * move_dist;
+                move_dist = 0.0;
+            }
+        }
+
+        self.path.is_empty()
+    }
+
+    // Updates the enemy's state and moves it, returns the amount of
+    // damage dealt to the player this frame (if it attacked)
+    pub fn update(
+        &mut self,
+        pos: &mut Vector2f64,
+        level: &Level,
+        manifest: &Manifest,
+        doors: &Doors,
+        player_pos: &Vector2f64,
+        dt: f64,
+    ) -> Option<f64> {
+        let player_dist = dist(pos, player_pos);
+        let can_see_player =
+            player_dist <= SIGHT_RANGE && line_of_sight(level, manifest, doors, pos, player_pos);
+        self.attack_timer -= dt;
+
+        if can_see_player {
+            self.last_seen = Some((player_pos.x.floor() as isize, player_pos.y.floor() as isize));
+        }
+
+        match self.state {
+            EnemyState::Idle => {
+                if can_see_player {
+                    self.state = EnemyState::Chasing;
+                    self.repath_timer = 0.0;
+                }
+            }
+            EnemyState::Chasing => {
+                if can_see_player && player_dist <= self.attack_range {
+                    self.state = EnemyState::Attacking;
+                    self.path.clear();
+                    return None;
+                }
+
+                // Head towards where the player was last seen
+                self.repath_timer -= dt;
+                if self.repath_timer <= 0.0 {
+                    self.repath_timer = REPATH_TIME;
+                    if let Some(goal) = self.last_seen {
+                        let start = (pos.x.floor() as isize, pos.y.floor() as isize);
+                        self.path = find_path(level, manifest, start, goal).unwrap_or_default();
+                    }
+                }
+
+                // Give up if the player isn't there anymore
+                if self.follow_path(pos, dt) && !can_see_player {
+                    self.state = EnemyState::Idle;
+                    self.last_seen = None;
+                }
+            }
+            EnemyState::Attacking => {
+                if !can_see_player || player_dist > self.attack_range {
+                    self.state = EnemyState::Chasing;
+                    self.repath_timer = 0.0;
+                    return None;
+                }
+
+                if self.attack_timer <= 0.0 {
+                    self.attack_timer = self.attack_time;
+                    return Some(self.attack_damage);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Two rooms split by a wall at x = 5
+    fn two_rooms() -> Level {
+        let mut level = Level::new(10, 5);
+        for y in 0..5 {
+            level.set_tile(5, y, 1);
+        }
+        level
+    }
+
+    fn update(enemy: &mut Enemy, pos: &mut Vector2f64, level: &Level, player: Vector2f64) {
+        let manifest = Manifest::default();
+        enemy.update(pos, level, &manifest, &Doors::default(), &player, 0.1);
+    }
+
+    #[test]
+    fn starts_chasing_when_it_sees_the_player() {
+        let level = two_rooms();
+        let mut enemy = Enemy::new(1.0, 1.5, 5.0, 1.0);
+        let mut pos = new_vector2(1.5, 2.5);
+
+        update(&mut enemy, &mut pos, &level, new_vector2(8.5, 2.5));
+        assert_eq!(enemy.state, EnemyState::Idle);
+
+        update(&mut enemy, &mut pos, &level, new_vector2(4.5, 2.5));
+        assert_eq!(enemy.state, EnemyState::Chasing);
+    }
+
+    #[test]
+    fn attacks_when_in_range() {
+        let level = two_rooms();
+        let mut enemy = Enemy::new(1.0, 1.5, 5.0, 1.0);
+        let mut pos = new_vector2(1.5, 2.5);
+        let player = new_vector2(4.5, 2.5);
+
+        update(&mut enemy, &mut pos, &level, player);
+        assert_eq!(enemy.state, EnemyState::Chasing);
+        for _ in 0..30 {
+            update(&mut enemy, &mut pos, &level, player);
+            if enemy.state != EnemyState::Chasing {
+                break;
+            }
+        }
+        assert_eq!(enemy.state, EnemyState::Attacking);
+        assert!(dist(&pos, &player) <= enemy.attack_range);
+
+        let damage = enemy.update(
+            &mut pos,
+            &level,
+            &Manifest::default(),
+            &Doors::default(),
+            &player,
+            0.1,
+        );
+        assert_eq!(damage, Some(5.0));
+    }
+
+    #[test]
+    fn goes_back_to_idle_after_losing_the_player() {
+        let level = two_rooms();
+        let mut enemy = Enemy::new(1.0, 1.5, 5.0, 1.0);
+        let mut pos = new_vector2(1.5, 2.5);
+
+        update(&mut enemy, &mut pos, &level, new_vector2(2.5, 2.5));
+        update(&mut enemy, &mut pos, &level, new_vector2(2.5, 2.5));
+        assert_eq!(enemy.state, EnemyState::Attacking);
+
+        // The player goes into the other room, the enemy checks where
+        // it last saw them and then gives up
+        let player = new_vector2(8.5, 2.5);
+        update(&mut enemy, &mut pos, &level, player);
+        assert_eq!(enemy.state, EnemyState::Chasing);
+        for _ in 0..30 {
+            update(&mut enemy, &mut pos, &level, player);
+            if enemy.state != EnemyState::Chasing {
+                break;
+            }
+        }
+        assert_eq!(enemy.state, EnemyState::Idle);
+        assert_eq!((pos.x, pos.y), (2.5, 2.5));
+    }
+}
@@ -16,6 +16,10 @@ pub struct InputState {
     mouse_button_state: HashMap<MouseButton, ButtonState>,
     mousex: i32,
     mousey: i32,
+    // Text that was typed since the last frame
+    text_input: String,
+    // How far the mouse moved since the last frame
+    mouse_motion: (i32, i32),
 }
 
 impl InputState {
@@ -25,6 +29,8 @@ impl InputState {
             mouse_button_state: HashMap::new(),
             mousex: 0,
             mousey: 0,
+            text_input: String::new(),
+            mouse_motion: (0, 0),
         }
     }
 
@@ -124,12 +130,31 @@ impl InputState {
     pub fn mouse_pos(&self) -> (i32, i32) {
         (self.mousex, self.mousey)
     }
+
+    pub fn text_input(&self) -> &str {
+        &self.text_input
+    }
+
+    pub fn mouse_motion(&self) -> (i32, i32) {
+        self.mouse_motion
+    }
 }
 
-pub fn can_quit(event_pump: &mut EventPump) -> bool {
+// Also records any text that was typed and how
+// far the mouse moved into the input state
+pub fn can_quit(event_pump: &mut EventPump, input_state: &mut InputState) -> bool {
+    input_state.text_input.clear();
+    input_state.mouse_motion = (0, 0);
+
     for event in event_pump.poll_iter() {
-        if let Event::Quit { .. } = event {
-            return true;
+        match event {
+            Event::Quit { .. } => return true,
+            Event::TextInput { text, .. } => input_state.text_input.push_str(&text),
+            Event::MouseMotion { xrel, yrel, .. } => {
+                input_state.mouse_motion.0 += xrel;
+                input_state.mouse_motion.1 += yrel;
+            }
+            _ => {}
         }
     }
 
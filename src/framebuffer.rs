@@ -0,0 +1,261 @@
+use sdl2::image::LoadSurface;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::surface::Surface;
+
+// RGBA pixel data of an image that has been loaded into memory,
+// used by the software renderer to sample textures without
+// needing a window or a renderer
+#[derive(Clone)]
+pub struct TextureData {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl TextureData {
+    pub fn from_surface(surface: &Surface) -> Result<Self, String> {
+        let converted = surface.convert_format(PixelFormatEnum::RGBA32)?;
+        let (width, height) = (converted.width(), converted.height());
+        let pitch = converted.pitch() as usize;
+
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        converted.with_lock(|data| {
+            for y in 0..height as usize {
+                let row_start = y * pitch;
+                pixels.extend_from_slice(&data[row_start..(row_start + width as usize * 4)]);
+            }
+        });
+
+        Ok(Self {
+            width,
+            height,
+            pixels,
+        })
+    }
+
+    // Magenta and black checkerboard that is used in place of
+    // images that are missing so that they are easy to spot
+    pub fn placeholder() -> Self {
+        let mut pixels = vec![];
+        for y in 0..16 {
+            for x in 0..16 {
+                if (x / 4 + y / 4) % 2 == 0 {
+                    pixels.extend_from_slice(&[255, 0, 255, 255]);
+                } else {
+                    pixels.extend_from_slice(&[0, 0, 0, 255]);
+                }
+            }
+        }
+
+        Self {
+            width: 16,
+            height: 16,
+            pixels,
+        }
+    }
+
+    // A 1 pixel wide image with a row for each color, for tests
+    #[cfg(test)]
+    pub fn stripes(colors: &[Color]) -> Self {
+        Self {
+            width: 1,
+            height: colors.len() as u32,
+            pixels: colors.iter().flat_map(|c| [c.r, c.g, c.b, c.a]).collect(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let surface = Surface::from_file(path)?;
+        Self::from_surface(&surface)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // RGBA bytes of the image, a row at a time
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        let x = x.min(self.width - 1) as usize;
+        let y = y.min(self.height - 1) as usize;
+        let index = (y * self.width as usize + x) * 4;
+        Color::RGBA(
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+            self.pixels[index + 3],
+        )
+    }
+}
+
+// Multiplies a color by `color_mod` the same way SDL's texture color mod
+// works and then mixes in the fog color, the alpha of `fog` is how much
+// of it gets mixed in (0 for none, 255 to replace the color completely)
+pub fn shade(color: Color, color_mod: Color, fog: Color) -> Color {
+    let amount = fog.a as u32;
+    let channel = |value: u8, modifier: u8, fog_value: u8| -> u8 {
+        let modded = value as u32 * modifier as u32 / 255;
+        ((modded * (255 - amount) + fog_value as u32 * amount) / 255) as u8
+    };
+
+    Color::RGBA(
+        channel(color.r, color_mod.r, fog.r),
+        channel(color.g, color_mod.g, fog.g),
+        channel(color.b, color_mod.b, fog.b),
+        color.a,
+    )
+}
+
+// An in-memory RGBA image that the level can be rendered to,
+// this allows frames to be drawn without a window
+pub struct FrameBuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl FrameBuffer {
+    pub fn new(w: u32, h: u32) -> Self {
+        Self {
+            width: w,
+            height: h,
+            pixels: vec![0u8; (w * h * 4) as usize],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    // RGBA bytes of the image, a row at a time
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn clear(&mut self, color: Color) {
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+    }
+
+    pub fn get_pixel(&self, x: u32, y: u32) -> Color {
+        let index = ((y * self.width + x) * 4) as usize;
+        Color::RGBA(
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+            self.pixels[index + 3],
+        )
+    }
+
+    // Overwrites a pixel without blending, used when the color is known to be opaque
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let index = ((y as u32 * self.width + x as u32) * 4) as usize;
+        self.pixels[index..(index + 4)].copy_from_slice(&[color.r, color.g, color.b, color.a]);
+    }
+
+    // Draws a pixel onto the buffer, alpha blending it with
+    // what is already there (this is the same as SDL's BlendMode::Blend)
+    pub fn blend_pixel(&mut self, x: i32, y: i32, color: Color) {
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 {
+            return;
+        }
+
+        let index = ((y as u32 * self.width + x as u32) * 4) as usize;
+        let alpha = color.a as u32;
+        let blend = |src: u8, dst: u8| -> u8 {
+            ((src as u32 * alpha + dst as u32 * (255 - alpha)) / 255) as u8
+        };
+
+        self.pixels[index] = blend(color.r, self.pixels[index]);
+        self.pixels[index + 1] = blend(color.g, self.pixels[index + 1]);
+        self.pixels[index + 2] = blend(color.b, self.pixels[index + 2]);
+        self.pixels[index + 3] =
+            (alpha + self.pixels[index + 3] as u32 * (255 - alpha) / 255) as u8;
+    }
+
+    // Stretches a column of a texture over the `dst` rectangle like a
+    // canvas copy would, only the part of the column inside `src` is used
+    // (its width is ignored) so that a frame out of a sprite sheet or a
+    // piece of a wall can be drawn. The texture is shaded with `color_mod`
+    // and `fog` (see shade)
+    pub fn copy_column_part(
+        &mut self,
+        texture: &TextureData,
+        src: Rect,
+        dst: Rect,
+        color_mod: Color,
+        fog: Color,
+    ) {
+        let top = dst.y() as i64;
+        let h = dst.height() as i64;
+        let starty = top.max(0);
+        let endy = (top + h).min(self.height as i64);
+
+        for y in starty..endy {
+            let src_y = src.y() as u32 + ((y - top) * src.height() as i64 / h) as u32;
+            let color = shade(texture.get_pixel(src.x() as u32, src_y), color_mod, fog);
+
+            for x in dst.left()..dst.right() {
+                self.blend_pixel(x, y as i32, color);
+            }
+        }
+    }
+
+    pub fn save_bmp(&self, path: &str) -> Result<(), String> {
+        let mut data = self.pixels.clone();
+        let surface = Surface::from_data(
+            &mut data,
+            self.width,
+            self.height,
+            self.width * 4,
+            PixelFormatEnum::RGBA32,
+        )?;
+        surface.save_bmp(path)
+    }
+
+    // Returns the number of pixels that differ from the image at `path`,
+    // used to check a rendered frame against a golden image
+    pub fn count_differences(&self, path: &str) -> Result<usize, String> {
+        let golden = TextureData::from_surface(&Surface::load_bmp(path)?)?;
+
+        if golden.width() != self.width || golden.height() != self.height {
+            return Err(format!(
+                "{path} is {}x{}, expected {}x{}",
+                golden.width(),
+                golden.height(),
+                self.width,
+                self.height
+            ));
+        }
+
+        let mut differences = 0;
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let expected = golden.get_pixel(x, y);
+                let actual = self.get_pixel(x, y);
+                if (expected.r, expected.g, expected.b) != (actual.r, actual.g, actual.b) {
+                    differences += 1;
+                }
+            }
+        }
+
+        Ok(differences)
+    }
+}
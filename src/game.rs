@@ -1,112 +1,1115 @@
-use crate::raycast::raycast;
+use crate::animation::{AnimationKind, SpriteDef};
+use crate::collision::move_circle;
+use crate::config::Config;
+use crate::door::Doors;
+use crate::enemy::{Enemy, EnemyState};
+use crate::framebuffer::{shade, FrameBuffer, TextureData};
+use crate::level::{TileLayer, OPEN_SKY};
+use crate::manifest::{Assets, Manifest, Pickup, SpriteProperties};
+use crate::menu::Text;
+use crate::player::Player;
+use crate::raycast::{line_of_sight, ray_hits_sprite, raycast, raycast_all, Ray, RaycastHit};
+use crate::sprite::{dist, Sprite, Vector2f64};
+use crate::viewport::Viewport;
+use crate::weapon::{Rng, WEAPONS};
 use crate::Camera;
 use crate::InputState;
 use crate::Level;
-use sdl2::pixels::Color;
-use sdl2::rect::{Point, Rect};
-use sdl2::render::{Canvas, Texture};
-use sdl2::video::Window;
+use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::ttf::Font;
+use sdl2::video::{Window, WindowContext};
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+use std::rc::Rc;
+
+// Width in pixels of each column that a ray is cast for
+pub const COLUMN_WIDTH: u32 = 2;
+
+// Everything needed to draw the level while it is being played, the level
+// is rendered in software and then copied onto the window with a texture
+pub struct GameView<'a> {
+    texture_creator: &'a TextureCreator<WindowContext>,
+    framebuffer: FrameBuffer,
+    // Streaming texture that the framebuffer is uploaded to every frame
+    texture: Texture<'a>,
+    assets: Assets,
+}
+
+impl<'a> GameView<'a> {
+    pub fn new(
+        texture_creator: &'a TextureCreator<WindowContext>,
+        assets: &Assets,
+    ) -> Result<Self, String> {
+        // The real size is set once the view is displayed
+        let texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, COLUMN_WIDTH, 1)
+            .map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            texture_creator,
+            framebuffer: FrameBuffer::new(COLUMN_WIDTH, 1),
+            texture,
+            assets: assets.clone(),
+        })
+    }
+
+    // Recreates the framebuffer and texture if the render size changed,
+    // the width is rounded down so that the columns fill the whole image
+    fn resize(&mut self, width: u32, height: u32) -> Result<(), String> {
+        let width = (width - width % COLUMN_WIDTH).max(COLUMN_WIDTH);
+        if (width, height) == (self.framebuffer.width(), self.framebuffer.height()) {
+            return Ok(());
+        }
+
+        self.texture = self
+            .texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGBA32, width, height)
+            .map_err(|e| e.to_string())?;
+        self.framebuffer = FrameBuffer::new(width, height);
+
+        Ok(())
+    }
+}
 
 pub fn display_level(
     canvas: &mut Canvas<Window>,
+    view: &mut GameView,
+    game_state: &GameState,
+    viewport: &Viewport,
+) -> Result<(), String> {
+    let (width, height) = viewport.render_size();
+    view.resize(width, height)?;
+
+    render_level(
+        &mut view.framebuffer,
+        &game_state.camera,
+        &game_state.level,
+        &game_state.doors,
+        &view.assets,
+        COLUMN_WIDTH,
+    );
+
+    let pitch = view.framebuffer.width() as usize * 4;
+    view.texture
+        .update(None, view.framebuffer.pixels(), pitch)
+        .map_err(|e| e.to_string())?;
+    canvas.copy(&view.texture, None, viewport.rect())?;
+
+    Ok(())
+}
+
+// How much walls facing north or south get darkened so that corners stand out
+const SIDE_BRIGHTNESS: f64 = 180.0 / 255.0;
+
+// Color mod and fog for something `distance` away from the camera that is lit by
+// the tile at (x, y), `face_brightness` is 1 apart from on the sides of walls
+pub fn lighting(
+    level: &Level,
+    x: f64,
+    y: f64,
+    distance: f64,
+    face_brightness: f64,
+) -> (Color, Color) {
+    let light = (level.brightness(x, y) * face_brightness * 255.0) as u8;
+    let [r, g, b] = level.fog_color;
+    let fog = (level.fog_amount(distance) * 255.0) as u8;
+    (Color::RGB(light, light, light), Color::RGBA(r, g, b, fog))
+}
+
+// Returns the color of a floor or ceiling texture at a point in the
+// level, None if the tile there is untextured
+fn sample_layer(
+    level: &Level,
+    textures: &[TextureData],
+    layer: TileLayer,
+    x: f64,
+    y: f64,
+) -> Option<Color> {
+    let tile = level.get_layer_tile(layer, x.floor() as isize, y.floor() as isize);
+    if tile == 0 || tile as usize > textures.len() {
+        return None;
+    }
+
+    let texture = &textures[tile as usize - 1];
+    Some(texture.get_pixel(
+        (x.fract() * texture.width() as f64) as u32,
+        (y.fract() * texture.height() as f64) as u32,
+    ))
+}
+
+// Draws the floor and the ceiling a row at a time, everything on a row is
+// the same distance away from the camera so the point in the level that
+// each pixel shows is found by moving that far along its column's ray
+fn render_floor_and_ceiling(
+    framebuffer: &mut FrameBuffer,
     camera: &Camera,
     level: &Level,
-    textures: &mut [Texture],
-    sprite_images: &[Texture],
+    textures: &[TextureData],
     line_width: u32,
-) -> Result<(), String> {
-    canvas.set_draw_color(Color::RGB(128, 128, 128));
-    canvas.fill_rect(Rect::new(80, 0, 800, 320))?;
-    canvas.set_draw_color(Color::RGB(64, 64, 64));
-    canvas.fill_rect(Rect::new(80, 320, 800, 320))?;
+) {
+    let (view_w, view_h) = (framebuffer.width(), framebuffer.height());
+    let [r, g, b] = level.floor_color;
+    let floor_color = Color::RGB(r, g, b);
+    let [r, g, b] = level.ceiling_color;
+    let ceiling_color = Color::RGB(r, g, b);
 
-    let mut depth_buffer = vec![0.0f64; (800 / line_width) as usize];
+    let angles: Vec<f64> = (0..(view_w / line_width))
+        .map(|i| {
+            -camera.fov / 2.0
+                + camera.rotation
+                + camera.fov / view_w as f64 * (i * line_width) as f64
+        })
+        .collect();
 
-    let mut angle = -camera.fov / 2.0 + camera.rotation;
-    for i in 0..(800 / line_width) {
-        let (hit, tile_type) = raycast(&camera.position, angle, 128.0, level);
-        let d = (hit.x - camera.position.x) * camera.rotation.cos()
-            + (hit.y - camera.position.y) * camera.rotation.sin();
+    // Ray direction of each column, scaled so that moving 1 unit along
+    // it moves 1 unit away from the camera (the same as the walls' depth)
+    let ray_dirs: Vec<(f64, f64)> = angles
+        .iter()
+        .map(|angle| {
+            let correction = (angle - camera.rotation).cos();
+            (angle.cos() / correction, angle.sin() / correction)
+        })
+        .collect();
 
-        if tile_type > 0 && (tile_type as usize) <= textures.len() && hit.x == hit.x.floor() {
-            let texture_properties = textures[tile_type as usize - 1].query();
+    // The sky wraps once around the camera, each column shows the
+    // part of it that is in the direction of the column's ray
+    let sky = match level.sky {
+        0 => None,
+        sky => textures.get(sky as usize - 1),
+    };
+    let sky_columns: Vec<u32> = match sky {
+        Some(sky) => angles
+            .iter()
+            .map(|angle| ((angle / (2.0 * PI)).rem_euclid(1.0) * sky.width() as f64) as u32)
+            .map(|x| x.min(sky.width() - 1))
+            .collect(),
+        None => vec![],
+    };
 
-            textures[tile_type as usize - 1].set_color_mod(255, 255, 255);
+    let horizon = view_h as f64 / 2.0;
+    for y in (view_h / 2)..view_h {
+        let row_dist = horizon / (y as f64 + 0.5 - horizon);
+        let ceiling_y = (view_h - 1 - y) as i32;
+        // The top of the sky is at the top of the view and the bottom is at the horizon
+        let sky_row = sky.map(|sky| {
+            let sky_y = (ceiling_y as f64 / horizon * sky.height() as f64) as u32;
+            (sky, sky_y.min(sky.height() - 1))
+        });
 
-            let sample_rect = Rect::new(
-                (hit.y.fract() * texture_properties.width as f64) as i32,
-                0,
-                1,
-                texture_properties.height,
-            );
+        for (i, (dir_x, dir_y)) in ray_dirs.iter().enumerate() {
+            let x = camera.position.x + dir_x * row_dist;
+            let y_pos = camera.position.y + dir_y * row_dist;
 
-            let dst_rect = Rect::from_center(
-                Point::new((i * line_width) as i32 + line_width as i32 / 2 + 80, 320),
-                line_width,
-                (640.0 / d) as u32,
+            let (color_mod, fog) = lighting(level, x, y_pos, row_dist, 1.0);
+            let floor =
+                sample_layer(level, textures, TileLayer::Floor, x, y_pos).unwrap_or(floor_color);
+            let floor = shade(floor, color_mod, fog);
+            // Open sky is far away so it doesn't get lit or fogged,
+            // levels without a sky use the ceiling color there instead
+            let ceiling_tile = level.get_layer_tile(
+                TileLayer::Ceiling,
+                x.floor() as isize,
+                y_pos.floor() as isize,
             );
+            let ceiling = match (ceiling_tile, sky_row) {
+                (OPEN_SKY, Some((sky, sky_y))) => sky.get_pixel(sky_columns[i], sky_y),
+                (OPEN_SKY, None) => shade(ceiling_color, color_mod, fog),
+                _ => shade(
+                    sample_layer(level, textures, TileLayer::Ceiling, x, y_pos)
+                        .unwrap_or(ceiling_color),
+                    color_mod,
+                    fog,
+                ),
+            };
 
-            canvas.copy(&textures[tile_type as usize - 1], sample_rect, dst_rect)?;
-        } else if tile_type > 0 && (tile_type as usize) <= textures.len() && hit.y == hit.y.floor()
-        {
-            let texture_properties = textures[tile_type as usize - 1].query();
+            let startx = (i as u32 * line_width) as i32;
+            for px in startx..(startx + line_width as i32) {
+                framebuffer.set_pixel(px, y as i32, floor);
+                framebuffer.set_pixel(px, ceiling_y, ceiling);
+            }
+        }
+    }
+}
 
-            textures[tile_type as usize - 1].set_color_mod(180, 180, 180);
+// Draws the part of a wall that a ray hit as column `i` of the view
+fn render_wall_column(
+    framebuffer: &mut FrameBuffer,
+    level: &Level,
+    textures: &[TextureData],
+    hit: &RaycastHit,
+    i: u32,
+    line_width: u32,
+) {
+    let texture = match textures.get(hit.tile as usize - 1) {
+        Some(texture) => texture,
+        None => return,
+    };
 
-            let sample_rect = Rect::new(
-                (hit.x.fract() * texture_properties.width as f64) as i32,
-                0,
-                1,
-                texture_properties.height,
-            );
+    let d = hit.perp_distance;
+    let face_brightness = if hit.face.is_east_west() {
+        1.0
+    } else {
+        SIDE_BRIGHTNESS
+    };
 
-            let dst_rect = Rect::from_center(
-                Point::new((i * line_width) as i32 + line_width as i32 / 2 + 80, 320),
-                line_width,
-                (640.0 / d) as u32,
+    // Walls are lit by the tile in front of them
+    let normal = hit.face.normal();
+    let (color_mod, fog) = lighting(
+        level,
+        hit.pos.x + normal.x * 0.01,
+        hit.pos.y + normal.y * 0.01,
+        d,
+        face_brightness,
+    );
+
+    // Walls stand on the floor, which is half a tile below the camera, and
+    // taller walls have their texture stacked on top of itself once for
+    // every tile of height (a shorter piece at the top shows the bottom
+    // of the texture)
+    let view_h = framebuffer.height() as f64;
+    let scale = view_h / d;
+    let floor_y = view_h / 2.0 + 0.5 * scale;
+    let height = level.wall_height(hit.x, hit.y);
+    let src_x = (hit.texture_u * texture.width() as f64) as i32;
+    let x = (i * line_width) as i32;
+
+    let mut bottom = 0.0;
+    while bottom < height {
+        let top = (bottom + 1.0).min(height);
+        let (top_y, bottom_y) = (
+            (floor_y - top * scale).round() as i32,
+            (floor_y - bottom * scale).round() as i32,
+        );
+
+        if bottom_y > top_y {
+            let src_y = ((1.0 - (top - bottom)) * texture.height() as f64) as u32;
+            framebuffer.copy_column_part(
+                texture,
+                Rect::new(src_x, src_y as i32, 1, texture.height() - src_y),
+                Rect::new(x, top_y, line_width, (bottom_y - top_y) as u32),
+                color_mod,
+                fog,
             );
+        }
+
+        bottom += 1.0;
+    }
+}
 
-            canvas.copy(&textures[tile_type as usize - 1], sample_rect, dst_rect)?;
+// Draws the see-through walls in each column that are further away than `depth`,
+// they are stored closest first so the furthest ones get drawn first
+fn render_see_through_walls(
+    framebuffer: &mut FrameBuffer,
+    level: &Level,
+    textures: &[TextureData],
+    see_through: &mut [Vec<RaycastHit>],
+    depth: f64,
+    line_width: u32,
+) {
+    for (i, hits) in see_through.iter_mut().enumerate() {
+        while hits.last().is_some_and(|hit| hit.perp_distance > depth) {
+            if let Some(hit) = hits.pop() {
+                render_wall_column(framebuffer, level, textures, &hit, i as u32, line_width);
+            }
+        }
+    }
+}
+
+// Draws the level into a framebuffer, the whole buffer is used as the viewport
+pub fn render_level(
+    framebuffer: &mut FrameBuffer,
+    camera: &Camera,
+    level: &Level,
+    doors: &Doors,
+    assets: &Assets,
+    line_width: u32,
+) {
+    let view_w = framebuffer.width();
+    let textures = &assets.textures;
+    framebuffer.clear(Color::BLACK);
+    render_floor_and_ceiling(framebuffer, camera, level, textures, line_width);
+
+    let columns = (view_w / line_width) as usize;
+    let mut depth_buffer = Vec::with_capacity(columns);
+    // Walls that can be seen through or over in each column, these are drawn
+    // along with the sprites so that sprites behind them are covered up
+    let mut see_through: Vec<Vec<RaycastHit>> = Vec::with_capacity(columns);
+
+    let opaque = |hit: &RaycastHit| {
+        !assets
+            .manifest
+            .tile(hit.tile)
+            .is_some_and(|tile| tile.transparent)
+    };
+    // How far up the view the top of a wall reaches, the camera is half a tile
+    // above the floor so this is 0 at the horizon and 0.5 at the top of the view
+    let top = |hit: &RaycastHit| (level.wall_height(hit.x, hit.y) - 0.5) / hit.perp_distance;
+    let max_height = level.max_wall_height();
+
+    let mut angle = -camera.fov / 2.0 + camera.rotation;
+    for i in 0..columns {
+        let ray = Ray::new(camera.position, angle, 128.0).viewed_from(camera.rotation);
+        // Highest that the walls hit so far cover the column up to, the ray stops
+        // once nothing further away could reach above them (or they reach the
+        // top of the view), the ray also stops when it leaves the level
+        let mut covered = f64::NEG_INFINITY;
+        let mut hides_behind = false;
+        let mut hits = raycast_all(&ray, level, doors, &assets.manifest, |hit| {
+            if opaque(hit) {
+                covered = covered.max(top(hit));
+                let highest_behind = ((max_height - 0.5) / hit.perp_distance).max(0.0);
+                hides_behind = covered >= highest_behind.min(0.5);
+            }
+            !hides_behind
+        });
+
+        // Only the last hit can be a wall that hides everything behind it, the
+        // others are only drawn if some of them shows above the walls in front
+        let wall = if hides_behind { hits.pop() } else { None };
+        let mut covered = f64::NEG_INFINITY;
+        hits.retain(|hit| {
+            let visible = top(hit) > covered;
+            if opaque(hit) {
+                covered = covered.max(top(hit));
+            }
+            visible
+        });
+        if let Some(wall) = &wall {
+            render_wall_column(framebuffer, level, textures, wall, i as u32, line_width);
         }
 
         // Update the depth buffer
-        depth_buffer[i as usize] = if tile_type > 0 { d } else { 9999.0 };
+        depth_buffer.push(wall.map(|wall| wall.perp_distance).unwrap_or(9999.0));
+        see_through.push(hits);
 
-        angle += camera.fov / 800.0 * line_width as f64;
+        angle += camera.fov / view_w as f64 * line_width as f64;
     }
 
+    // The sprites are sorted so that the furthest ones are drawn first
     for sprite in &level.sprites {
-        sprite.display(
-            canvas,
+        let depth = sprite.view_depth(camera);
+        render_see_through_walls(
+            framebuffer,
+            level,
+            textures,
+            &mut see_through,
+            depth,
+            line_width,
+        );
+
+        sprite.render(
+            framebuffer,
             &depth_buffer,
             camera,
-            &sprite_images[sprite.sprite_type as usize - 1],
-        )?;
+            level,
+            &assets.sprites[sprite.sprite_type as usize - 1],
+            &assets.sprite_defs[sprite.sprite_type as usize - 1],
+        );
     }
 
-    Ok(())
+    render_see_through_walls(
+        framebuffer,
+        level,
+        textures,
+        &mut see_through,
+        f64::NEG_INFINITY,
+        line_width,
+    );
 }
 
-pub fn game_update(level: &mut Level, camera: &mut Camera, input_state: &InputState, dt: f64) {
-    //Handle player collision with any tiles
-    let dist_travelled = camera.speed.abs() * dt;
-    //Cast the ray in the direction the player is moving
-    let ray_angle = if camera.speed > 0.0 {
-        camera.rotation //Player is moving forward
+// Sort the sprites so that the ones furthest from the camera get drawn first
+pub fn sort_sprites(sprites: &mut [Sprite], camera: &Camera) {
+    sprites.sort_by(|sprite1, sprite2| {
+        let sprite1_trans_x = sprite1.pos.x - camera.position.x;
+        let sprite1_trans_y = sprite1.pos.y - camera.position.y;
+        let sprite1_rotated_y =
+            sprite1_trans_x * (-camera.rotation).cos() - sprite1_trans_y * (-camera.rotation).sin();
+
+        let sprite2_trans_x = sprite2.pos.x - camera.position.x;
+        let sprite2_trans_y = sprite2.pos.y - camera.position.y;
+        let sprite2_rotated_y =
+            sprite2_trans_x * (-camera.rotation).cos() - sprite2_trans_y * (-camera.rotation).sin();
+
+        if sprite2_rotated_y < sprite1_rotated_y {
+            Ordering::Less
+        } else {
+            Ordering::Greater
+        }
+    });
+}
+
+// How close the player has to be to a sprite to pick it up
+const PICKUP_DISTANCE: f64 = 0.5;
+// How far away the player can be from a door and still open it
+const USE_DISTANCE: f64 = 1.0;
+// How long messages stay on the screen for (in seconds)
+const MESSAGE_TIME: f64 = 2.0;
+// How much ammo a bullets pickup gives
+const BULLETS_AMMO: u32 = 10;
+// How much health a health pickup gives
+const HEALTH_AMOUNT: f64 = 25.0;
+// How long the death screen is shown before the player can respawn (in seconds)
+const RESPAWN_DELAY: f64 = 1.0;
+// How close a shot has to pass to a sprite to hit it
+const SPRITE_HIT_RADIUS: f64 = 0.3;
+// Explosions do the most damage at the center and none at the edge
+const EXPLOSION_RADIUS: f64 = 3.0;
+const EXPLOSION_DAMAGE: f64 = 60.0;
+// How long a barrel takes to go off after being caught in an explosion
+const CHAIN_DELAY: f64 = 0.25;
+const EXPLOSIVE_HEALTH: f64 = 10.0;
+
+// How much health a sprite that can be shot starts with,
+// None if the sprite can't be shot
+fn starting_health(properties: &SpriteProperties) -> Option<f64> {
+    if let Some(enemy) = properties.enemy {
+        Some(enemy.health())
+    } else if properties.explosive {
+        Some(EXPLOSIVE_HEALTH)
     } else {
-        camera.rotation + std::f64::consts::PI //Player is moving backwards
+        None
+    }
+}
+
+// Everything that changes while the level is being played, the level
+// is a copy of the one in the editor so that picking up items and
+// opening doors doesn't change the level that is being edited
+pub struct GameState {
+    pub level: Level,
+    pub camera: Camera,
+    pub player: Player,
+    pub config: Config,
+    // What each texture and sprite does
+    pub manifest: Rc<Manifest>,
+    // How far open each door is
+    pub doors: Doors,
+    // How each type of sprite is animated, shared with the renderer
+    pub sprite_defs: Rc<[SpriteDef]>,
+    message: String,
+    message_timer: f64,
+    rng: Rng,
+    // How long the player has been dead for
+    death_timer: f64,
+    // Set once the player uses an exit
+    completed: bool,
+}
+
+impl GameState {
+    // Starts the level with the player at the spawn point
+    pub fn new(level: &Level, config: Config, assets: &Assets) -> Self {
+        let mut level = level.clone();
+        for sprite in &mut level.sprites {
+            let properties = assets.manifest.sprite_properties(sprite.sprite_type);
+            sprite.enemy = properties.enemy.map(Enemy::from_kind);
+            sprite.health = starting_health(&properties);
+        }
+
+        Self {
+            camera: Self::spawn_camera(&level),
+            doors: Doors::new(&level, &assets.manifest),
+            level,
+            player: Player::new(),
+            config,
+            manifest: assets.manifest.clone(),
+            sprite_defs: assets.sprite_defs.clone(),
+            message: String::new(),
+            message_timer: 0.0,
+            rng: Rng::new(),
+            death_timer: 0.0,
+            completed: false,
+        }
+    }
+
+    fn spawn_camera(level: &Level) -> Camera {
+        Camera::new(level.spawnx, level.spawny, 0.0, PI / 12.0 * 5.0)
+    }
+
+    // Puts the player back at the spawn point, the level stays as it was
+    fn respawn(&mut self) {
+        self.camera = Self::spawn_camera(&self.level);
+        self.player.respawn();
+        self.death_timer = 0.0;
+    }
+
+    pub fn show_message(&mut self, msg: &str) {
+        self.message = msg.to_owned();
+        self.message_timer = MESSAGE_TIME;
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        if self.message_timer > 0.0 {
+            Some(&self.message)
+        } else {
+            None
+        }
+    }
+}
+
+// Picks up any keys, ammo and health that the player is standing on
+fn pick_up_items(game_state: &mut GameState) {
+    let mut i = 0;
+    while i < game_state.level.sprites.len() {
+        let sprite = &game_state.level.sprites[i];
+        if dist(&sprite.pos, &game_state.camera.position) >= PICKUP_DISTANCE {
+            i += 1;
+            continue;
+        }
+
+        let player = &mut game_state.player;
+        let message = match game_state
+            .manifest
+            .sprite_properties(sprite.sprite_type)
+            .pickup
+        {
+            Some(Pickup::Key(key)) => {
+                player.give_key(key);
+                Some(format!("Picked up the {} key", key.name()))
+            }
+            Some(Pickup::Ammo) if player.give_ammo(BULLETS_AMMO) => {
+                Some(format!("Picked up {BULLETS_AMMO} bullets"))
+            }
+            Some(Pickup::Health) if player.give_health(HEALTH_AMOUNT) => {
+                Some(format!("Picked up {HEALTH_AMOUNT} health"))
+            }
+            _ => None,
+        };
+
+        match message {
+            Some(message) => {
+                game_state.level.sprites.remove(i);
+                game_state.show_message(&message);
+            }
+            None => i += 1,
+        }
+    }
+}
+
+// Casts a ray for each pellet of the current weapon and damages
+// the closest sprite that each ray hits before it hits a wall
+fn fire_weapon(game_state: &mut GameState) {
+    let weapon = game_state.player.weapon();
+    game_state.player.ammo -= weapon.ammo_per_shot;
+    game_state.player.fire_timer = 1.0 / weapon.fire_rate;
+
+    let camera = &game_state.camera;
+    for _ in 0..weapon.pellets {
+        let angle = camera.rotation + game_state.rng.next_signed() * weapon.spread;
+
+        let ray = Ray::new(camera.position, angle, weapon.range);
+        let manifest = &game_state.manifest;
+        let wall_dist = raycast(
+            &ray,
+            &game_state.level,
+            &game_state.doors,
+            manifest,
+            |tile| manifest.tile_is_opaque(tile),
+        )
+        .map(|hit| hit.distance)
+        .unwrap_or(weapon.range);
+
+        let target = game_state
+            .level
+            .sprites
+            .iter_mut()
+            .filter(|sprite| sprite.health.is_some())
+            .filter_map(|sprite| {
+                ray_hits_sprite(&camera.position, angle, &sprite.pos, SPRITE_HIT_RADIUS)
+                    .map(|d| (d, sprite))
+            })
+            .filter(|(d, _)| *d < wall_dist)
+            .min_by(|(d1, _), (d2, _)| d1.total_cmp(d2));
+
+        if let Some((_, sprite)) = target {
+            sprite.health = sprite.health.map(|health| health - weapon.damage);
+        }
+    }
+
+    remove_destroyed_sprites(game_state);
+}
+
+fn handle_weapon_input(game_state: &mut GameState, input_state: &InputState) {
+    let weapon_keys = [Scancode::Num1, Scancode::Num2];
+    for (i, key) in weapon_keys.iter().enumerate().take(WEAPONS.len()) {
+        if input_state.key_is_clicked(*key) && game_state.player.weapon != i {
+            game_state.player.weapon = i;
+            game_state.show_message(&format!("Switched to the {}", WEAPONS[i].name));
+        }
+    }
+
+    let trigger_held = input_state.key_is_held(Scancode::LCtrl)
+        || input_state.mouse_button_is_held(MouseButton::Left);
+    let trigger_clicked = input_state.key_is_clicked(Scancode::LCtrl)
+        || input_state.mouse_button_is_clicked(MouseButton::Left);
+
+    if !trigger_held || game_state.player.fire_timer > 0.0 {
+        return;
+    }
+
+    if game_state.player.ammo < game_state.player.weapon().ammo_per_shot {
+        if trigger_clicked {
+            game_state.show_message("Out of ammo");
+        }
+        return;
+    }
+
+    fire_weapon(game_state);
+}
+
+fn update_enemies(game_state: &mut GameState, dt: f64) {
+    // Take the sprites out of the level so that the enemies
+    // can look at the level while they are being moved
+    let mut sprites = std::mem::take(&mut game_state.level.sprites);
+    let mut damage = 0.0;
+
+    let player_pos = game_state.camera.position;
+    for sprite in &mut sprites {
+        if let Some(enemy) = &mut sprite.enemy {
+            let start = sprite.pos;
+            damage += enemy
+                .update(
+                    &mut sprite.pos,
+                    &game_state.level,
+                    &game_state.manifest,
+                    &game_state.doors,
+                    &player_pos,
+                    dt,
+                )
+                .unwrap_or(0.0);
+
+            // Enemies face the way they are walking or the player while attacking
+            let (dx, dy) = match enemy.state {
+                EnemyState::Attacking => (player_pos.x - sprite.pos.x, player_pos.y - sprite.pos.y),
+                _ => (sprite.pos.x - start.x, sprite.pos.y - start.y),
+            };
+            if dx != 0.0 || dy != 0.0 {
+                sprite.facing = dy.atan2(dx);
+            }
+
+            sprite.animation.play(match enemy.state {
+                EnemyState::Idle => AnimationKind::Idle,
+                EnemyState::Chasing => AnimationKind::Walk,
+                EnemyState::Attacking => AnimationKind::Attack,
+            });
+        }
+    }
+
+    game_state.level.sprites = sprites;
+    damage_player(game_state, damage);
+}
+
+fn damage_player(game_state: &mut GameState, damage: f64) {
+    if damage > 0.0 && !game_state.player.is_dead() {
+        game_state.player.take_damage(damage);
+    }
+}
+
+// Removes anything that has been destroyed, barrels are set to explode
+// instead of being removed straight away and enemies with a death
+// animation leave a body behind
+fn remove_destroyed_sprites(game_state: &mut GameState) {
+    for sprite in &mut game_state.level.sprites {
+        let destroyed = sprite.health.map(|health| health <= 0.0).unwrap_or(false);
+        if !destroyed {
+            continue;
+        }
+
+        let def = game_state.sprite_defs.get(sprite.sprite_type as usize - 1);
+        let properties = game_state.manifest.sprite_properties(sprite.sprite_type);
+        if properties.explosive && sprite.fuse.is_none() {
+            sprite.fuse = Some(0.0);
+        } else if sprite.enemy.is_some()
+            && def.is_some_and(|def| def.has_animation(AnimationKind::Death))
+        {
+            sprite.enemy = None;
+            sprite.health = None;
+            sprite.animation.play(AnimationKind::Death);
+        }
+    }
+
+    game_state.level.sprites.retain(|sprite| {
+        sprite.fuse.is_some() || sprite.health.map(|health| health > 0.0).unwrap_or(true)
+    });
+}
+
+// Damages everything within the explosion radius that isn't behind a wall
+// and sets off any barrels that are caught in it
+fn explode(game_state: &mut GameState, center: &Vector2f64) {
+    // Take the sprites out so the level can be used for line of sight checks
+    let mut sprites = std::mem::take(&mut game_state.level.sprites);
+    let damage_at = |pos: &Vector2f64| {
+        let d = dist(center, pos);
+        if d < EXPLOSION_RADIUS
+            && line_of_sight(
+                &game_state.level,
+                &game_state.manifest,
+                &game_state.doors,
+                center,
+                pos,
+            )
+        {
+            Some(EXPLOSION_DAMAGE * (1.0 - d / EXPLOSION_RADIUS))
+        } else {
+            None
+        }
     };
 
-    let (hit_pos, tile) = raycast(&camera.position, ray_angle, dist_travelled, level);
-    if tile == 0 {
-        //If the player doesn't hit any tile, just move it as normal
-        camera.update(dt);
-    } else {
-        //Otherwise, only move it very close to the tile they hit
-        camera.position.x = hit_pos.x - ray_angle.cos() * 0.01;
-        camera.position.y = hit_pos.y - ray_angle.sin() * 0.01;
+    let player_damage = damage_at(&game_state.camera.position).unwrap_or(0.0);
+
+    for sprite in &mut sprites {
+        if sprite.health.is_none() {
+            continue;
+        }
+
+        if let Some(damage) = damage_at(&sprite.pos) {
+            sprite.health = sprite.health.map(|health| health - damage);
+            let properties = game_state.manifest.sprite_properties(sprite.sprite_type);
+            if properties.explosive && sprite.fuse.is_none() {
+                sprite.fuse = Some(CHAIN_DELAY);
+            }
+        }
+    }
+    game_state.level.sprites = sprites;
+
+    damage_player(game_state, player_damage);
+}
+
+// Counts down the fuses of barrels that have been set
+// off and blows up the ones that have run out
+fn update_barrels(game_state: &mut GameState, dt: f64) {
+    let mut explosions = vec![];
+    game_state
+        .level
+        .sprites
+        .retain_mut(|sprite| match &mut sprite.fuse {
+            Some(fuse) if *fuse <= 0.0 => {
+                explosions.push(sprite.pos);
+                false
+            }
+            Some(fuse) => {
+                *fuse -= dt;
+                true
+            }
+            None => true,
+        });
+
+    for center in &explosions {
+        explode(game_state, center);
+    }
+
+    remove_destroyed_sprites(game_state);
+}
+
+// Uses the tile that the player is facing, doors get opened, switches
+// open the tiles that they are linked to and exits finish the level
+fn use_tile(game_state: &mut GameState) {
+    let camera = &game_state.camera;
+    let manifest = game_state.manifest.clone();
+    let ray = Ray::new(camera.position, camera.rotation, USE_DISTANCE);
+    let hit = match raycast(
+        &ray,
+        &game_state.level,
+        &game_state.doors,
+        &manifest,
+        |tile| manifest.tile(tile).is_some(),
+    ) {
+        Some(hit) => hit,
+        None => return,
+    };
+    let properties = match manifest.tile(hit.tile) {
+        Some(properties) => properties,
+        None => return,
+    };
+
+    if properties.door {
+        match properties.key {
+            Some(key) if !game_state.player.has_key(key) => {
+                game_state.show_message(&format!("You need the {} key", key.name()));
+            }
+            _ => game_state.doors.open(hit.x, hit.y),
+        }
+    }
+
+    if let Some(target) = properties.switch_target {
+        if manifest.tile(target).is_some_and(|tile| tile.door) {
+            game_state.doors.open_all(target);
+        } else {
+            game_state.level.replace_tiles(target, 0);
+        }
+    }
+
+    if properties.exit {
+        game_state.completed = true;
+    }
+}
+
+// Opens and closes doors, they don't close on the player or enemies
+fn update_doors(game_state: &mut GameState, dt: f64) {
+    let radius = game_state.player.radius;
+    let mut occupants = vec![(game_state.camera.position, radius)];
+    occupants.extend(
+        game_state
+            .level
+            .sprites
+            .iter()
+            .filter(|sprite| sprite.enemy.is_some())
+            .map(|sprite| (sprite.pos, radius)),
+    );
+
+    game_state
+        .doors
+        .update(&mut game_state.level, &occupants, dt);
+}
+
+// Damage per second from the tiles that the player is standing on
+fn tile_damage(game_state: &GameState) -> f64 {
+    let pos = &game_state.camera.position;
+    let (x, y) = (pos.x.floor() as isize, pos.y.floor() as isize);
+    [TileLayer::Walls, TileLayer::Floor]
+        .iter()
+        .filter_map(|layer| {
+            game_state
+                .manifest
+                .tile(game_state.level.get_layer_tile(*layer, x, y))
+        })
+        .map(|tile| tile.damage)
+        .fold(0.0, f64::max)
+}
+
+pub fn display_hud(
+    canvas: &mut Canvas<Window>,
+    game_state: &GameState,
+    sprite_images: &[Texture],
+    texture_creator: &TextureCreator<WindowContext>,
+    font: &Font,
+    viewport: &Viewport,
+) -> Result<(), String> {
+    let center = viewport.center();
+    let bottom = viewport.bottom();
+
+    // Show the keys that the player is holding on the left side of the screen
+    let key_sprites = game_state
+        .player
+        .keys
+        .iter()
+        .filter_map(|key| game_state.manifest.key_sprite(*key));
+    for (i, sprite) in key_sprites.enumerate() {
+        canvas.copy(
+            &sprite_images[sprite as usize - 1],
+            game_state
+                .sprite_defs
+                .get(sprite as usize - 1)
+                .map(SpriteDef::icon_rect),
+            Rect::new(viewport.x + 8, viewport.y + 8 + i as i32 * 72, 64, 64),
+        )?;
+    }
+
+    // Crosshair in the center of the view
+    canvas.set_draw_color(Color::WHITE);
+    canvas.fill_rect(Rect::from_center(center, 12, 2))?;
+    canvas.fill_rect(Rect::from_center(center, 2, 12))?;
+
+    let player = &game_state.player;
+    let health = format!("health {:.0} armor {:.0}", player.health, player.armor);
+    Text::new(&health, Color::WHITE, viewport.x + 16, bottom - 32, 12).display(
+        canvas,
+        texture_creator,
+        font,
+    )?;
+
+    let ammo = format!("{} {}", player.weapon().name, player.ammo);
+    let mut text = Text::new(&ammo, Color::WHITE, 0, bottom - 32, 12);
+    text.x = viewport.right() - 16 - text.width() as i32;
+    text.display(canvas, texture_creator, font)?;
+
+    if player.is_dead() {
+        canvas.set_draw_color(Color::RGBA(128, 0, 0, 128));
+        canvas.fill_rect(viewport.rect())?;
+
+        let mut text = Text::new("You died", Color::WHITE, 0, center.y - 40, 24);
+        text.x = center.x - text.width() as i32 / 2;
+        text.display(canvas, texture_creator, font)?;
+
+        if game_state.death_timer > RESPAWN_DELAY {
+            let mut text = Text::new("Press Space to respawn", Color::WHITE, 0, center.y + 24, 12);
+            text.x = center.x - text.width() as i32 / 2;
+            text.display(canvas, texture_creator, font)?;
+        }
+    }
+
+    if game_state.completed {
+        let mut text = Text::new("Level complete", Color::WHITE, 0, center.y - 40, 24);
+        text.x = center.x - text.width() as i32 / 2;
+        text.display(canvas, texture_creator, font)?;
+    }
+
+    if let Some(msg) = game_state.message() {
+        // Center the message at the bottom of the screen
+        let mut text = Text::new(msg, Color::WHITE, 0, bottom - 80, 12);
+        text.x = center.x - text.width() as i32 / 2;
+        text.display(canvas, texture_creator, font)?;
+    }
+
+    Ok(())
+}
+
+pub fn game_update(game_state: &mut GameState, input_state: &InputState, dt: f64) {
+    game_state.message_timer -= dt;
+
+    if game_state.completed {
+        return;
+    }
+
+    if game_state.player.is_dead() {
+        game_state.death_timer += dt;
+        if game_state.death_timer > RESPAWN_DELAY && input_state.key_is_clicked(Scancode::Space) {
+            game_state.respawn();
+        }
+        return;
     }
 
-    //Get key input to move the camera
-    camera.handle_key_input(input_state);
+    let camera = &mut game_state.camera;
+    let start = camera.position;
+    let movement = camera.update(dt, &game_state.config);
+    move_circle(
+        &game_state.level,
+        &game_state.manifest,
+        &mut camera.position,
+        movement.x,
+        movement.y,
+        game_state.player.radius,
+    );
+    // Lose any speed that went into a wall so that the player
+    // doesn't keep pushing against it after turning away
+    if dt > 0.0 {
+        camera.velocity.x = (camera.position.x - start.x) / dt;
+        camera.velocity.y = (camera.position.y - start.y) / dt;
+    }
+
+    //Get input to move the camera
+    camera.handle_input(input_state, &game_state.config);
+
+    for sprite in &mut game_state.level.sprites {
+        sprite.animation.update(dt);
+    }
+
+    pick_up_items(game_state);
+    let damage = tile_damage(game_state) * dt;
+    damage_player(game_state, damage);
+    update_enemies(game_state, dt);
+    handle_weapon_input(game_state, input_state);
+    update_barrels(game_state, dt);
+    update_doors(game_state, dt);
+
+    if input_state.key_is_clicked(Scancode::E) || input_state.key_is_clicked(Scancode::Space) {
+        use_tile(game_state);
+    }
+
+    game_state.player.fire_timer -= dt;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLOOR: Color = Color::RGB(64, 64, 64);
+    const CEILING: Color = Color::RGB(128, 128, 128);
+    const GREEN: Color = Color::RGB(0, 255, 0);
+    const RED: Color = Color::RGB(255, 0, 0);
+    const BLUE: Color = Color::RGB(0, 0, 255);
+    const YELLOW: Color = Color::RGB(255, 255, 0);
+
+    // Texture 1 is green, 2 is red on top of blue and 3 is yellow
+    fn assets() -> Assets {
+        Assets {
+            manifest: Rc::new(Manifest::default()),
+            textures: vec![
+                TextureData::stripes(&[GREEN]),
+                TextureData::stripes(&[RED, BLUE]),
+                TextureData::stripes(&[YELLOW]),
+            ],
+            sprites: vec![],
+            sprite_defs: Rc::from(vec![]),
+        }
+    }
+
+    // A room with yellow walls that is looked at from (1.5, 3.5) facing
+    // east, on a 64x64 view so the middle column goes straight down y = 3.5
+    fn room() -> Level {
+        let mut level = Level::new(12, 7);
+        for x in 0..12 {
+            level.set_tile(x, 0, 3);
+            level.set_tile(x, 6, 3);
+        }
+        for y in 0..7 {
+            level.set_tile(0, y, 3);
+            level.set_tile(11, y, 3);
+        }
+        level
+    }
+
+    // Colors down the middle column of the view
+    fn middle_column(level: &Level) -> Vec<Color> {
+        let mut framebuffer = FrameBuffer::new(64, 64);
+        let camera = Camera::new(1.5, 3.5, 0.0, PI / 12.0 * 5.0);
+        render_level(
+            &mut framebuffer,
+            &camera,
+            level,
+            &Doors::default(),
+            &assets(),
+            1,
+        );
+        (0..64).map(|y| framebuffer.get_pixel(32, y)).collect()
+    }
+
+    #[test]
+    fn walls_can_be_seen_over_low_walls() {
+        let mut level = room();
+        // A half height wall 1.5 tiles away in front of a full one 5.5 away
+        for y in 1..6 {
+            level.set_tile(3, y, 1);
+            level.set_layer_tile(TileLayer::Height, 3, y, 2);
+            level.set_tile(7, y, 2);
+        }
+        // A tall pillar somewhere else doesn't let walls behind
+        // the full height wall show through
+        level.set_tile(9, 1, 1);
+        level.set_layer_tile(TileLayer::Height, 9, 1, 12);
+
+        // The low wall's top is at the horizon (the camera is at half a
+        // tile) and the top half of the far wall sticks out above it
+        let column = middle_column(&level);
+        assert!(column[20] == CEILING);
+        assert!(column[27] == RED);
+        assert!(column[31] == RED);
+        assert!(!column.contains(&BLUE));
+        assert!(column[33] == GREEN);
+        assert!(column[52] == GREEN);
+        assert!(column[60] == FLOOR);
+        assert!(!column.contains(&YELLOW));
+    }
+
+    #[test]
+    fn tall_walls_stack_their_texture() {
+        let mut level = room();
+        // A 2 tile tall wall 3.5 tiles away
+        for y in 1..6 {
+            level.set_tile(5, y, 2);
+            level.set_layer_tile(TileLayer::Height, 5, y, 8);
+        }
+
+        // It goes from about row 5 to 41, with the texture drawn twice
+        let column = middle_column(&level);
+        assert!(column[2] == CEILING);
+        assert!(column[8] == RED);
+        assert!(column[18] == BLUE);
+        assert!(column[27] == RED);
+        assert!(column[37] == BLUE);
+        assert!(column[45] == FLOOR);
+        assert!(!column.contains(&YELLOW));
+    }
 }
@@ -1,12 +1,65 @@
 use crate::sprite::Sprite;
 
+// Number of different light levels, the light layer stores how
+// dark each tile is from 0 (fully lit) to LIGHT_LEVELS - 1
+pub const LIGHT_LEVELS: u8 = 8;
+// Number of different wall heights, the height layer stores how tall the
+// wall in each tile is in quarters of a tile (0 is the normal height of 1)
+pub const HEIGHT_LEVELS: u8 = 13;
+// Ceiling tiles with this value are open sky instead of a texture, so
+// texture 255 can't be used on the ceiling
+pub const OPEN_SKY: u8 = 255;
+
+// Every tile has a wall, a floor and a ceiling texture (0 means
+// no wall, or an untextured floor/ceiling), a light level and a wall height
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TileLayer {
+    Walls,
+    Floor,
+    Ceiling,
+    Light,
+    Height,
+}
+
+impl TileLayer {
+    // Layers that are painted with textures from the manifest
+    pub fn is_textured(&self) -> bool {
+        matches!(self, Self::Walls | Self::Floor | Self::Ceiling)
+    }
+}
+
+fn height_from_quarters(quarters: u8) -> f64 {
+    match quarters {
+        0 => 1.0,
+        quarters => quarters as f64 / 4.0,
+    }
+}
+
+#[derive(Clone)]
 pub struct Level {
     pub width: u32,
     pub height: u32,
     level_data: Vec<u8>,
+    floor_data: Vec<u8>,
+    ceiling_data: Vec<u8>,
+    light_data: Vec<u8>,
+    height_data: Vec<u8>,
+    // Colors used where the floor or ceiling is untextured
+    pub floor_color: [u8; 3],
+    pub ceiling_color: [u8; 3],
+    // Things fade into the fog color as they get further away, fog_distance
+    // is how far away they are completely hidden (0 turns the fog off)
+    pub fog_color: [u8; 3],
+    pub fog_distance: f64,
+    // Texture drawn where the ceiling is open sky (OPEN_SKY), it wraps once
+    // around the camera as it turns (0 draws the ceiling color instead)
+    pub sky: u8,
     pub spawnx: f64,
     pub spawny: f64,
     pub sprites: Vec<Sprite>,
+    // Path of the manifest that the level's textures and sprites come from
+    // (relative to the level file), None to use the default one
+    pub manifest: Option<String>,
 }
 
 impl Level {
@@ -15,9 +68,19 @@ impl Level {
             width: w,
             height: h,
             level_data: vec![0u8; (w * h) as usize],
+            floor_data: vec![0u8; (w * h) as usize],
+            ceiling_data: vec![0u8; (w * h) as usize],
+            light_data: vec![0u8; (w * h) as usize],
+            height_data: vec![0u8; (w * h) as usize],
+            floor_color: [64, 64, 64],
+            ceiling_color: [128, 128, 128],
+            fog_color: [0, 0, 0],
+            fog_distance: 0.0,
+            sky: 0,
             spawnx: 0.0,
             spawny: 0.0,
             sprites: vec![],
+            manifest: None,
         }
     }
 
@@ -25,24 +88,92 @@ impl Level {
         x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize
     }
 
-    pub fn get_tile(&self, x: isize, y: isize) -> u8 {
+    fn layer_data(&self, layer: TileLayer) -> &Vec<u8> {
+        match layer {
+            TileLayer::Walls => &self.level_data,
+            TileLayer::Floor => &self.floor_data,
+            TileLayer::Ceiling => &self.ceiling_data,
+            TileLayer::Light => &self.light_data,
+            TileLayer::Height => &self.height_data,
+        }
+    }
+
+    fn layer_data_mut(&mut self, layer: TileLayer) -> &mut Vec<u8> {
+        match layer {
+            TileLayer::Walls => &mut self.level_data,
+            TileLayer::Floor => &mut self.floor_data,
+            TileLayer::Ceiling => &mut self.ceiling_data,
+            TileLayer::Light => &mut self.light_data,
+            TileLayer::Height => &mut self.height_data,
+        }
+    }
+
+    pub fn get_layer_tile(&self, layer: TileLayer, x: isize, y: isize) -> u8 {
         if self.out_of_bounds(x, y) {
             return 0;
         }
 
-        self.level_data[(self.width as usize) * y as usize + x as usize]
+        self.layer_data(layer)[(self.width as usize) * y as usize + x as usize]
     }
 
-    pub fn set_tile(&mut self, x: isize, y: isize, tile: u8) {
+    pub fn set_layer_tile(&mut self, layer: TileLayer, x: isize, y: isize, tile: u8) {
         if self.out_of_bounds(x, y) {
             return;
         }
 
-        self.level_data[(self.width as usize) * y as usize + x as usize] = tile;
+        let index = (self.width as usize) * y as usize + x as usize;
+        self.layer_data_mut(layer)[index] = tile;
+    }
+
+    pub fn get_tile(&self, x: isize, y: isize) -> u8 {
+        self.get_layer_tile(TileLayer::Walls, x, y)
+    }
+
+    pub fn set_tile(&mut self, x: isize, y: isize, tile: u8) {
+        self.set_layer_tile(TileLayer::Walls, x, y, tile);
+    }
+
+    // Replaces every wall tile of one type with another
+    pub fn replace_tiles(&mut self, from: u8, to: u8) {
+        for tile in self.level_data.iter_mut().filter(|tile| **tile == from) {
+            *tile = to;
+        }
+    }
+
+    // How bright the tile at a point is, from 1 (fully lit) down towards 0
+    pub fn brightness(&self, x: f64, y: f64) -> f64 {
+        let darkness =
+            self.get_layer_tile(TileLayer::Light, x.floor() as isize, y.floor() as isize);
+        1.0 - darkness as f64 / LIGHT_LEVELS as f64
+    }
+
+    // How tall the wall in a tile is, 1 is the same height as the ceiling
+    pub fn wall_height(&self, x: isize, y: isize) -> f64 {
+        height_from_quarters(self.get_layer_tile(TileLayer::Height, x, y))
+    }
+
+    // Height of the tallest wall in the level, nothing behind a wall
+    // can reach higher up the view than this
+    pub fn max_wall_height(&self) -> f64 {
+        self.level_data
+            .iter()
+            .zip(&self.height_data)
+            .filter(|(tile, _)| **tile != 0)
+            .map(|(_, height)| height_from_quarters(*height))
+            .fold(0.0, f64::max)
+    }
+
+    // How much of the fog color gets mixed in at a distance, from 0 to 1
+    pub fn fog_amount(&self, distance: f64) -> f64 {
+        if self.fog_distance <= 0.0 {
+            return 0.0;
+        }
+
+        (distance / self.fog_distance).clamp(0.0, 1.0)
     }
 
-    pub fn level_data_bytes(&self) -> &[u8] {
-        &self.level_data
+    pub fn layer_bytes(&self, layer: TileLayer) -> &[u8] {
+        self.layer_data(layer)
     }
 
     pub fn place_sprite(&mut self, spr: Sprite) {
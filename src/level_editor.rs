@@ -1,54 +1,199 @@
+use crate::level::{TileLayer, HEIGHT_LEVELS, LIGHT_LEVELS, OPEN_SKY};
 use crate::InputState;
 use crate::Level;
+use sdl2::keyboard::Scancode;
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
 use sdl2::rect::Rect;
-use sdl2::render::{Canvas, Texture};
+use sdl2::render::Canvas;
 use sdl2::video::Window;
 
-use self::level_editor_menu::EditorMode;
-use super::sprite::Sprite;
+use self::history::EditHistory;
+use self::level_editor_menu::{EditorMode, EditorTextures, LevelEditorMenu};
 
+pub mod file_dialog;
+pub mod history;
 pub mod level_editor_menu;
 pub mod level_file;
+pub mod level_text_file;
+
+// Biggest size that tiles are drawn at in the editor (in pixels)
+const MAX_TILE_SIZE: f64 = 16.0;
+
+// Where the tiles of the level are drawn in the editor, levels that are too
+// big for the space to the left of the menu get shrunk down to fit in it
+#[derive(Clone, Copy)]
+pub struct EditorGrid {
+    tile_size: f64,
+}
+
+impl EditorGrid {
+    pub fn fit(level: &Level, width: u32, height: u32) -> Self {
+        let tile_size = (width as f64 / level.width as f64)
+            .min(height as f64 / level.height as f64)
+            .min(MAX_TILE_SIZE);
+        Self { tile_size }
+    }
+
+    // Edges are rounded separately so that there aren't any gaps between
+    // the tiles when they aren't a whole number of pixels wide, rounding
+    // up keeps them lined up with the pixels that tile_at gives back
+    pub fn tile_rect(&self, x: isize, y: isize) -> Rect {
+        let edge = |i: isize| (i as f64 * self.tile_size).ceil() as i32;
+        Rect::new(
+            edge(x),
+            edge(y),
+            (edge(x + 1) - edge(x)).max(1) as u32,
+            (edge(y + 1) - edge(y)).max(1) as u32,
+        )
+    }
+
+    // Tile that a point on the screen is over
+    fn tile_at(&self, x: i32, y: i32) -> (isize, isize) {
+        (
+            (x as f64 / self.tile_size).floor() as isize,
+            (y as f64 / self.tile_size).floor() as isize,
+        )
+    }
+}
 
 pub fn display_level_editor(
     canvas: &mut Canvas<Window>,
     level: &Level,
     input_state: &InputState,
-    textures: &[Texture],
-    sprite_images: &[Texture],
+    editor_textures: &EditorTextures,
+    grid: &EditorGrid,
+    layer: TileLayer,
 ) -> Result<(), String> {
+    let untextured_color = match layer {
+        TileLayer::Walls | TileLayer::Light | TileLayer::Height => None,
+        TileLayer::Floor => Some(level.floor_color),
+        TileLayer::Ceiling => Some(level.ceiling_color),
+    };
+    // Light levels and wall heights are shown on top of the walls
+    let shown_layer = match layer {
+        TileLayer::Light | TileLayer::Height => TileLayer::Walls,
+        _ => layer,
+    };
+
     for y in 0..level.height {
         for x in 0..level.width {
-            canvas.set_draw_color(Color::WHITE);
-            canvas.draw_rect(Rect::new(x as i32 * 16, y as i32 * 16, 16, 16))?;
+            let tile_rect = grid.tile_rect(x as isize, y as isize);
+            let tile = level.get_layer_tile(shown_layer, x as isize, y as isize);
 
-            if level.get_tile(x as isize, y as isize) != 0 {
+            if shown_layer == TileLayer::Ceiling && tile == OPEN_SKY {
+                canvas.copy(&editor_textures.open_sky, None, tile_rect)?;
+            } else if tile != 0 {
                 canvas.copy(
-                    &textures[level.get_tile(x as isize, y as isize) as usize - 1],
+                    &editor_textures.textures[tile as usize - 1],
                     None,
-                    Rect::new(x as i32 * 16, y as i32 * 16, 16, 16),
+                    tile_rect,
                 )?;
+            } else if let Some([r, g, b]) = untextured_color {
+                canvas.set_draw_color(Color::RGB(r, g, b));
+                canvas.fill_rect(tile_rect)?;
+            }
+
+            if layer == TileLayer::Light {
+                let darkness = level.get_layer_tile(layer, x as isize, y as isize);
+                let alpha = darkness as u32 * 255 / LIGHT_LEVELS as u32;
+                canvas.set_draw_color(Color::RGBA(0, 0, 0, alpha as u8));
+                canvas.fill_rect(tile_rect)?;
+            } else if layer == TileLayer::Height {
+                // A bar on the left of the tile that fills it at the tallest height
+                let quarters = level.get_layer_tile(layer, x as isize, y as isize);
+                if quarters != 0 {
+                    let bar_h = quarters as u32 * tile_rect.height() / (HEIGHT_LEVELS as u32 - 1);
+                    canvas.set_draw_color(Color::CYAN);
+                    canvas.fill_rect(Rect::new(
+                        tile_rect.x(),
+                        tile_rect.bottom() - bar_h.max(1) as i32,
+                        (tile_rect.width() / 4).max(1),
+                        bar_h.max(1),
+                    ))?;
+                }
+            } else if layer != TileLayer::Walls && level.get_tile(x as isize, y as isize) != 0 {
+                // Darken the walls while painting the floor or ceiling
+                // so that it's clear where the rooms are
+                canvas.set_draw_color(Color::RGBA(0, 0, 0, 160));
+                canvas.fill_rect(tile_rect)?;
             }
+
+            canvas.set_draw_color(Color::WHITE);
+            canvas.draw_rect(tile_rect)?;
         }
     }
 
     for sprite in &level.sprites {
+        let index = sprite.sprite_type as usize - 1;
         canvas.copy(
-            &sprite_images[sprite.sprite_type as usize - 1],
-            None,
-            Rect::new(sprite.pos.x as i32 * 16, sprite.pos.y as i32 * 16, 16, 16),
+            &editor_textures.sprite_images[index],
+            editor_textures.sprite_frames.get(index).copied(),
+            grid.tile_rect(sprite.pos.x as isize, sprite.pos.y as isize),
         )?;
     }
 
     let (mousex, mousey) = input_state.mouse_pos();
-    canvas.set_draw_color(Color::YELLOW);
-    canvas.draw_rect(Rect::new(mousex / 16 * 16, mousey / 16 * 16, 16, 16))?;
+    let (tilex, tiley) = grid.tile_at(mousex, mousey);
+    if !level.out_of_bounds(tilex, tiley) {
+        canvas.set_draw_color(Color::YELLOW);
+        canvas.draw_rect(grid.tile_rect(tilex, tiley))?;
+    }
 
     Ok(())
 }
 
+// Editing state that needs to be kept between frames
+pub struct EditorState {
+    pub history: EditHistory,
+    pub grid: EditorGrid,
+    // Tile that gets painted while the mouse is dragged, this is
+    // None when the left mouse button isn't held down
+    stroke_tile: Option<u8>,
+}
+
+impl EditorState {
+    pub fn new() -> Self {
+        Self {
+            history: EditHistory::new(),
+            grid: EditorGrid {
+                tile_size: MAX_TILE_SIZE,
+            },
+            stroke_tile: None,
+        }
+    }
+
+    // Starts a drag stroke, everything it paints is undone in one step
+    fn begin_stroke(&mut self, tile: u8) {
+        self.history.begin_group();
+        self.stroke_tile = Some(tile);
+    }
+
+    fn paint_stroke(&mut self, level: &mut Level, layer: TileLayer, x: isize, y: isize) {
+        if let Some(tile) = self.stroke_tile {
+            self.history.set_tile(level, layer, x, y, tile);
+        }
+    }
+
+    fn end_stroke(&mut self) {
+        if self.stroke_tile.take().is_some() {
+            self.history.end_group();
+        }
+    }
+
+    // Undoing or redoing in the middle of a stroke ends it, otherwise the
+    // rest of the drag would be added to the history one tile at a time
+    pub fn undo(&mut self, level: &mut Level) {
+        self.end_stroke();
+        self.history.undo(level);
+    }
+
+    pub fn redo(&mut self, level: &mut Level) {
+        self.end_stroke();
+        self.history.redo(level);
+    }
+}
+
 fn invert_tile(current: u8, selected: u8) -> u8 {
     if current == 0 {
         selected
@@ -67,49 +212,165 @@ fn search_for_sprite_at_positon(level: &Level, x: f64, y: f64) -> Option<usize>
     None
 }
 
-fn handle_mouse_sprite_mode(level: &mut Level, mousex: f64, mousey: f64, selected: u8) {
+fn handle_mouse_sprite_mode(
+    level: &mut Level,
+    history: &mut EditHistory,
+    mousex: f64,
+    mousey: f64,
+    selected: u8,
+) {
     let spr_index = search_for_sprite_at_positon(level, mousex + 0.5, mousey + 0.5);
 
-    if mousex as u32 > level.width || mousey as u32 > level.height || mousex < 0.0 || mousey < 0.0 {
+    if level.out_of_bounds(mousex as isize, mousey as isize) {
         return;
     }
 
     match spr_index {
         Some(i) => {
-            level.sprites.remove(i);
+            history.remove_sprite(level, i);
         }
         _ => {
-            level.place_sprite(Sprite::new(mousex + 0.5, mousey + 0.5, selected));
+            history.add_sprite(level, mousex + 0.5, mousey + 0.5, selected);
         }
     }
 }
 
+// Replaces the area of connected tiles that are the same as
+// the tile at (x, y) with `tile`, this is undone in one step
+fn flood_fill(
+    level: &mut Level,
+    history: &mut EditHistory,
+    layer: TileLayer,
+    (x, y): (isize, isize),
+    tile: u8,
+) {
+    let target = level.get_layer_tile(layer, x, y);
+    if level.out_of_bounds(x, y) || target == tile {
+        return;
+    }
+
+    history.begin_group();
+
+    let mut stack = vec![(x, y)];
+    while let Some((x, y)) = stack.pop() {
+        if level.out_of_bounds(x, y) || level.get_layer_tile(layer, x, y) != target {
+            continue;
+        }
+
+        history.set_tile(level, layer, x, y, tile);
+        stack.push((x + 1, y));
+        stack.push((x - 1, y));
+        stack.push((x, y + 1));
+        stack.push((x, y - 1));
+    }
+
+    history.end_group();
+}
+
 pub fn handle_mouse_input_editor(
     level: &mut Level,
     input_state: &InputState,
-    selected: u8,
-    editor_mode: &EditorMode,
+    level_editor_menu: &LevelEditorMenu,
+    editor_state: &mut EditorState,
 ) {
+    let (selected, layer) = (level_editor_menu.selected, level_editor_menu.layer);
     let (mousex, mousey) = input_state.mouse_pos();
-    let (mousex, mousey) = (mousex as isize / 16, mousey as isize / 16);
+    let (mousex, mousey) = editor_state.grid.tile_at(mousex, mousey);
+    // Clicking on the menu's buttons shouldn't also edit the level
+    let over_menu = level_editor_menu.menu.mouse_hovering(input_state);
 
-    if input_state.mouse_button_is_clicked(MouseButton::Left) {
-        match editor_mode {
+    if input_state.mouse_button_is_clicked(MouseButton::Left) && !over_menu {
+        match level_editor_menu.editor_mode {
             EditorMode::Tiles => {
-                level.set_tile(
-                    mousex,
-                    mousey,
-                    invert_tile(level.get_tile(mousex, mousey), selected),
-                );
+                if input_state.key_is_held(Scancode::F) {
+                    let history = &mut editor_state.history;
+                    flood_fill(level, history, layer, (mousex, mousey), selected);
+                } else if !level.out_of_bounds(mousex, mousey) {
+                    // Start a stroke, dragging the mouse keeps placing (or
+                    // erasing) tiles until the button is released
+                    let current = level.get_layer_tile(layer, mousex, mousey);
+                    editor_state.begin_stroke(invert_tile(current, selected));
+                }
             }
             EditorMode::Sprites => {
-                handle_mouse_sprite_mode(level, mousex as f64, mousey as f64, selected);
+                let history = &mut editor_state.history;
+                handle_mouse_sprite_mode(level, history, mousex as f64, mousey as f64, selected);
             }
         }
     }
 
-    if input_state.mouse_button_is_clicked(MouseButton::Right) {
-        level.spawnx = mousex as f64 + 0.5;
-        level.spawny = mousey as f64 + 0.5;
+    if !input_state.mouse_button_is_held(MouseButton::Left) {
+        editor_state.end_stroke();
+    } else if !over_menu {
+        editor_state.paint_stroke(level, layer, mousex, mousey);
+    }
+
+    if input_state.mouse_button_is_clicked(MouseButton::Right)
+        && !over_menu
+        && !level.out_of_bounds(mousex, mousey)
+    {
+        editor_state
+            .history
+            .move_spawn(level, mousex as f64 + 0.5, mousey as f64 + 0.5);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_mid_stroke_ends_the_stroke() {
+        let mut level = Level::new(4, 1);
+        let mut editor_state = EditorState::new();
+
+        editor_state.begin_stroke(1);
+        editor_state.paint_stroke(&mut level, TileLayer::Walls, 0, 0);
+        editor_state.paint_stroke(&mut level, TileLayer::Walls, 1, 0);
+        editor_state.undo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 0, 0, 0]);
+
+        // The rest of the drag doesn't paint anything, so it can't
+        // fill up the history with a step for every tile
+        editor_state.paint_stroke(&mut level, TileLayer::Walls, 2, 0);
+        editor_state.paint_stroke(&mut level, TileLayer::Walls, 3, 0);
+        editor_state.end_stroke();
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 0, 0, 0]);
+
+        // Redoing brings back the whole stroke at once
+        editor_state.redo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn stroke_is_undone_in_one_step() {
+        let mut level = Level::new(3, 1);
+        let mut editor_state = EditorState::new();
+
+        editor_state.begin_stroke(2);
+        for x in 0..3 {
+            editor_state.paint_stroke(&mut level, TileLayer::Walls, x, 0);
+        }
+        editor_state.end_stroke();
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [2, 2, 2]);
+
+        editor_state.undo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 0, 0]);
+    }
+
+    #[test]
+    fn grid_fits_next_to_the_menu() {
+        // Small levels keep their normal size
+        let grid = EditorGrid::fit(&Level::new(10, 10), 320, 480);
+        assert_eq!(grid.tile_rect(9, 9), Rect::new(144, 144, 16, 16));
+
+        // Big ones are shrunk so that every tile can still be clicked on
+        let level = Level::new(100, 300);
+        let grid = EditorGrid::fit(&level, 320, 480);
+        let last = grid.tile_rect(99, 299);
+        assert!(last.right() <= 320 && last.bottom() <= 480);
+        assert_eq!(grid.tile_at(last.x(), last.y()), (99, 299));
+        assert_eq!(grid.tile_at(last.right() - 1, last.bottom() - 1), (99, 299));
+        assert_eq!(grid.tile_at(0, 0), (0, 0));
     }
 }
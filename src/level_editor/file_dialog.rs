@@ -0,0 +1,428 @@
+use crate::menu::{MenuElement, Text};
+use crate::InputState;
+use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::render::{Canvas, TextureCreator};
+use sdl2::ttf::Font;
+use sdl2::video::{Window, WindowContext};
+use std::path::{Path, PathBuf};
+
+const FILES_PER_PAGE: usize = 8;
+// Files with these extensions are shown in the file list
+const LEVEL_EXTENSIONS: [&str; 2] = ["lvl", "txt"];
+// Extension that is added when saving a file without one
+const DEFAULT_EXTENSION: &str = "lvl";
+// Characters that fit on a line of the dialog at the size messages are drawn at
+const MESSAGE_LINE_LENGTH: usize = 44;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum FileDialogMode {
+    Save,
+    Open,
+}
+
+pub enum FileDialogAction {
+    None,
+    Cancel,
+    Save(String),
+    Open(String),
+}
+
+pub struct FileDialog {
+    pub mode: FileDialogMode,
+    pub filename: String,
+    directory: PathBuf,
+    files: Vec<String>,
+    page: usize,
+    // Set when saving over a file that already exists
+    confirm_overwrite: bool,
+    // Shown instead of the file list until it's dismissed
+    error: Option<String>,
+    // Center of the window, the dialog is drawn here
+    center: Point,
+}
+
+// Returns the names of all the level files in a directory, sorted
+fn list_level_files(directory: &Path) -> Result<Vec<String>, String> {
+    let mut files: Vec<String> = match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_file())
+            .filter(|path| {
+                path.extension()
+                    .and_then(|ext| ext.to_str())
+                    .map(|ext| LEVEL_EXTENSIONS.contains(&ext))
+                    .unwrap_or(false)
+            })
+            .filter_map(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .map(|name| name.to_owned())
+            })
+            .collect(),
+        Err(msg) => return Err(format!("Failed to list {}: {msg}", directory.display())),
+    };
+
+    files.sort();
+    Ok(files)
+}
+
+// Splits a message into lines that fit in the dialog, words that are
+// too long for a line (like paths) are split wherever they need to be
+fn wrap_message(message: &str) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+
+    for word in message.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        if !line.is_empty() && line.chars().count() + 1 + word.len() > MESSAGE_LINE_LENGTH {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        while line.chars().count() + word.len() > MESSAGE_LINE_LENGTH {
+            let rest = word.split_off(MESSAGE_LINE_LENGTH - line.chars().count());
+            line.extend(word);
+            lines.push(std::mem::take(&mut line));
+            word = rest;
+        }
+        line.extend(word);
+    }
+
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn button(id: &str, label: &str, x: i32, y: i32, w: u32) -> MenuElement {
+    let mut button = MenuElement::new(x, y, w, 32, Color::RGB(48, 48, 48), Color::RGB(64, 64, 64));
+    button.set_id(id);
+    button.text.push(Text::new(label, Color::WHITE, 8, 6, 10));
+    button
+}
+
+impl FileDialog {
+    // Opens the dialog in the same directory as the current file
+    // (or the working directory if there is no current file)
+    pub fn new(dialog_mode: FileDialogMode, current_file: Option<&str>) -> Self {
+        let current_path = current_file.map(Path::new);
+
+        let directory = current_path
+            .and_then(|path| path.parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_else(|| PathBuf::from("."));
+
+        let filename = current_path
+            .and_then(|path| path.file_name())
+            .and_then(|name| name.to_str())
+            .map(|name| name.to_owned())
+            .unwrap_or_default();
+
+        let (files, error) = match list_level_files(&directory) {
+            Ok(files) => (files, None),
+            Err(msg) => (vec![], Some(msg)),
+        };
+
+        Self {
+            mode: dialog_mode,
+            filename,
+            directory,
+            files,
+            page: 0,
+            confirm_overwrite: false,
+            error,
+            center: Point::new(480, 320),
+        }
+    }
+
+    // Shows why saving or opening failed, the dialog stays open so
+    // another file can be picked once the message is dismissed
+    pub fn show_error(&mut self, message: &str) {
+        self.confirm_overwrite = false;
+        self.error = Some(message.to_owned());
+    }
+
+    // Keeps the dialog in the middle of the window when it gets resized
+    pub fn set_center(&mut self, x: i32, y: i32) {
+        self.center = Point::new(x, y);
+    }
+
+    fn page_count(&self) -> usize {
+        self.files.len().div_ceil(FILES_PER_PAGE)
+    }
+
+    fn path(&self) -> String {
+        self.directory
+            .join(&self.filename)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn build_menu(&self) -> MenuElement {
+        let mut dialog = MenuElement::new(
+            self.center.x,
+            self.center.y,
+            480,
+            480,
+            Color::RGB(32, 32, 32),
+            Color::RGB(32, 32, 32),
+        );
+
+        let title = match self.mode {
+            FileDialogMode::Save => "Save Level",
+            FileDialogMode::Open => "Open Level",
+        };
+        dialog.text.push(Text::new(title, Color::WHITE, 16, 16, 12));
+
+        if let Some(error) = &self.error {
+            for (i, line) in wrap_message(error).iter().take(14).enumerate() {
+                dialog.text.push(Text::new(
+                    line,
+                    Color::RGB(255, 96, 96),
+                    16,
+                    80 + i as i32 * 24,
+                    10,
+                ));
+            }
+            dialog
+                .children
+                .push(button("error_ok", "OK", 16 + 60, 480 - 32, 120));
+            return dialog;
+        }
+
+        if self.confirm_overwrite {
+            let message = format!("{} already exists.", self.filename);
+            dialog
+                .text
+                .push(Text::new(&message, Color::WHITE, 16, 80, 10));
+            dialog
+                .text
+                .push(Text::new("Overwrite it?", Color::WHITE, 16, 112, 10));
+            dialog
+                .children
+                .push(button("overwrite_yes", "Yes", 16 + 60, 480 - 32, 120));
+            dialog.children.push(button(
+                "overwrite_no",
+                "No",
+                16 + 60 + 120 + 16,
+                480 - 32,
+                120,
+            ));
+            return dialog;
+        }
+
+        // Text box with the name of the file
+        {
+            let mut filename_box = MenuElement::new(
+                240,
+                80,
+                448,
+                32,
+                Color::RGB(16, 16, 16),
+                Color::RGB(16, 16, 16),
+            );
+            let filename = format!("{}_", self.filename);
+            filename_box
+                .text
+                .push(Text::new(&filename, Color::WHITE, 8, 6, 10));
+            dialog.children.push(filename_box);
+        }
+
+        // List of level files in the directory
+        let first_file = self.page * FILES_PER_PAGE;
+        for (i, file) in self
+            .files
+            .iter()
+            .enumerate()
+            .skip(first_file)
+            .take(FILES_PER_PAGE)
+        {
+            let normal_color = if *file == self.filename {
+                Color::RGB(64, 64, 96)
+            } else {
+                Color::RGB(32, 32, 32)
+            };
+
+            let mut file_button = MenuElement::new(
+                240,
+                128 + (i - first_file) as i32 * 32,
+                448,
+                28,
+                normal_color,
+                Color::RGB(48, 48, 48),
+            );
+            file_button.set_id(&format!("file_{i}"));
+            file_button
+                .text
+                .push(Text::new(file, Color::WHITE, 8, 4, 10));
+            dialog.children.push(file_button);
+        }
+
+        if self.page_count() > 1 {
+            let page = format!("{}/{}", self.page + 1, self.page_count());
+            dialog
+                .text
+                .push(Text::new(&page, Color::WHITE, 220, 480 - 96, 10));
+            dialog
+                .children
+                .push(button("prev_page", "<", 16 + 24, 480 - 80, 48));
+            dialog
+                .children
+                .push(button("next_page", ">", 480 - 16 - 24, 480 - 80, 48));
+        }
+
+        let confirm_label = match self.mode {
+            FileDialogMode::Save => "Save",
+            FileDialogMode::Open => "Open",
+        };
+        dialog
+            .children
+            .push(button("dialog_ok", confirm_label, 16 + 60, 480 - 32, 120));
+        dialog.children.push(button(
+            "dialog_cancel",
+            "Cancel",
+            16 + 60 + 120 + 16,
+            480 - 32,
+            120,
+        ));
+
+        dialog
+    }
+
+    pub fn display(
+        &self,
+        canvas: &mut Canvas<Window>,
+        input_state: &InputState,
+        texture_creator: &TextureCreator<WindowContext>,
+        font: &Font,
+    ) -> Result<(), String> {
+        let menu = self.build_menu();
+        menu.display_with_children(canvas, input_state)?;
+        menu.display_text_with_children(canvas, texture_creator, font)?;
+        Ok(())
+    }
+
+    fn confirm(&mut self) -> FileDialogAction {
+        if self.filename.is_empty() {
+            return FileDialogAction::None;
+        }
+
+        match self.mode {
+            FileDialogMode::Save => {
+                if Path::new(&self.filename).extension().is_none() {
+                    self.filename = format!("{}.{DEFAULT_EXTENSION}", self.filename);
+                }
+
+                if Path::new(&self.path()).exists() && !self.confirm_overwrite {
+                    self.confirm_overwrite = true;
+                    return FileDialogAction::None;
+                }
+
+                FileDialogAction::Save(self.path())
+            }
+            FileDialogMode::Open => FileDialogAction::Open(self.path()),
+        }
+    }
+
+    pub fn update(&mut self, input_state: &InputState) -> FileDialogAction {
+        let clicked = self
+            .build_menu()
+            .get_clicked(input_state, MouseButton::Left)
+            .unwrap_or("".to_owned());
+
+        if self.error.is_some() {
+            if clicked == "error_ok"
+                || input_state.key_is_clicked(Scancode::Return)
+                || input_state.key_is_clicked(Scancode::Escape)
+            {
+                self.error = None;
+            }
+
+            return FileDialogAction::None;
+        }
+
+        if self.confirm_overwrite {
+            if clicked == "overwrite_yes" || input_state.key_is_clicked(Scancode::Return) {
+                return FileDialogAction::Save(self.path());
+            } else if clicked == "overwrite_no" || input_state.key_is_clicked(Scancode::Escape) {
+                self.confirm_overwrite = false;
+            }
+
+            return FileDialogAction::None;
+        }
+
+        // Typing into the file name box
+        self.filename.push_str(input_state.text_input());
+        if input_state.key_is_clicked(Scancode::Backspace) {
+            self.filename.pop();
+        }
+
+        if let Some(index) = clicked.strip_prefix("file_") {
+            if let Some(file) = index.parse::<usize>().ok().and_then(|i| self.files.get(i)) {
+                self.filename = file.clone();
+            }
+        } else if clicked == "prev_page" {
+            self.page = self.page.saturating_sub(1);
+        } else if clicked == "next_page" {
+            self.page = (self.page + 1).min(self.page_count().saturating_sub(1));
+        } else if clicked == "dialog_ok" || input_state.key_is_clicked(Scancode::Return) {
+            return self.confirm();
+        } else if clicked == "dialog_cancel" || input_state.key_is_clicked(Scancode::Escape) {
+            return FileDialogAction::Cancel;
+        }
+
+        FileDialogAction::None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_messages_are_wrapped_to_fit_the_dialog() {
+        let lines = wrap_message(
+            "Failed to save level: /home/someone/levels/a/very/long/path/to/the/level.lvl \
+             is a directory",
+        );
+        assert_eq!(
+            lines,
+            [
+                "Failed to save level:",
+                "/home/someone/levels/a/very/long/path/to/the",
+                "/level.lvl is a directory",
+            ]
+        );
+        assert!(lines.iter().all(|line| line.len() <= MESSAGE_LINE_LENGTH));
+    }
+
+    #[test]
+    fn directories_that_cant_be_listed_show_an_error() {
+        let missing = std::env::temp_dir().join("retro-fps-missing-directory/level.lvl");
+        let mut dialog = FileDialog::new(FileDialogMode::Open, missing.to_str());
+        assert!(dialog.files.is_empty());
+        assert!(dialog.error.as_ref().unwrap().starts_with("Failed to list"));
+
+        // The message stays up until it's dismissed
+        assert!(matches!(
+            dialog.update(&InputState::new()),
+            FileDialogAction::None
+        ));
+        assert!(dialog.error.is_some());
+    }
+
+    #[test]
+    fn errors_replace_the_overwrite_question() {
+        let mut dialog = FileDialog::new(FileDialogMode::Save, None);
+        dialog.confirm_overwrite = true;
+        dialog.show_error("Failed to save level: Permission denied");
+        assert!(!dialog.confirm_overwrite);
+        assert!(dialog.error.is_some());
+    }
+}
@@ -0,0 +1,252 @@
+use crate::level::TileLayer;
+use crate::sprite::Sprite;
+use crate::Level;
+
+// A single change made to the level in the editor, each
+// command stores enough information for it to be reversed
+#[derive(Clone, Copy)]
+pub enum EditCommand {
+    SetTile {
+        layer: TileLayer,
+        x: isize,
+        y: isize,
+        old: u8,
+        new: u8,
+    },
+    AddSprite {
+        x: f64,
+        y: f64,
+        sprite_type: u8,
+    },
+    RemoveSprite {
+        x: f64,
+        y: f64,
+        sprite_type: u8,
+    },
+    MoveSpawn {
+        old: (f64, f64),
+        new: (f64, f64),
+    },
+}
+
+impl EditCommand {
+    fn apply(&self, level: &mut Level) {
+        match *self {
+            Self::SetTile {
+                layer, x, y, new, ..
+            } => level.set_layer_tile(layer, x, y, new),
+            Self::AddSprite { x, y, sprite_type } => {
+                level.place_sprite(Sprite::new(x, y, sprite_type));
+            }
+            Self::RemoveSprite { x, y, sprite_type } => {
+                // The sprites get sorted every frame so look for the
+                // sprite by its position instead of its index
+                let index = level.sprites.iter().position(|sprite| {
+                    sprite.pos.x == x && sprite.pos.y == y && sprite.sprite_type == sprite_type
+                });
+
+                if let Some(i) = index {
+                    level.sprites.remove(i);
+                }
+            }
+            Self::MoveSpawn { new, .. } => (level.spawnx, level.spawny) = new,
+        }
+    }
+
+    fn inverse(&self) -> Self {
+        match *self {
+            Self::SetTile {
+                layer,
+                x,
+                y,
+                old,
+                new,
+            } => Self::SetTile {
+                layer,
+                x,
+                y,
+                old: new,
+                new: old,
+            },
+            Self::AddSprite { x, y, sprite_type } => Self::RemoveSprite { x, y, sprite_type },
+            Self::RemoveSprite { x, y, sprite_type } => Self::AddSprite { x, y, sprite_type },
+            Self::MoveSpawn { old, new } => Self::MoveSpawn { old: new, new: old },
+        }
+    }
+}
+
+// Undo and redo stacks for the level editor, each entry is a list of
+// commands so that a bulk action (a drag stroke, a flood fill) can
+// be undone in one step
+pub struct EditHistory {
+    undo_stack: Vec<Vec<EditCommand>>,
+    redo_stack: Vec<Vec<EditCommand>>,
+    // Commands that are currently being grouped into a single step
+    group: Option<Vec<EditCommand>>,
+}
+
+impl EditHistory {
+    pub fn new() -> Self {
+        Self {
+            undo_stack: vec![],
+            redo_stack: vec![],
+            group: None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.undo_stack.clear();
+        self.redo_stack.clear();
+        self.group = None;
+    }
+
+    // Applies a command to the level and records it
+    pub fn execute(&mut self, level: &mut Level, command: EditCommand) {
+        command.apply(level);
+        self.redo_stack.clear();
+
+        match &mut self.group {
+            Some(group) => group.push(command),
+            None => self.undo_stack.push(vec![command]),
+        }
+    }
+
+    // Any commands executed until end_group is called are undone together
+    pub fn begin_group(&mut self) {
+        self.end_group();
+        self.group = Some(vec![]);
+    }
+
+    pub fn end_group(&mut self) {
+        if let Some(group) = self.group.take() {
+            if !group.is_empty() {
+                self.undo_stack.push(group);
+            }
+        }
+    }
+
+    pub fn set_tile(&mut self, level: &mut Level, layer: TileLayer, x: isize, y: isize, tile: u8) {
+        let old = level.get_layer_tile(layer, x, y);
+        if level.out_of_bounds(x, y) || old == tile {
+            return;
+        }
+
+        self.execute(
+            level,
+            EditCommand::SetTile {
+                layer,
+                x,
+                y,
+                old,
+                new: tile,
+            },
+        );
+    }
+
+    pub fn add_sprite(&mut self, level: &mut Level, x: f64, y: f64, sprite_type: u8) {
+        self.execute(level, EditCommand::AddSprite { x, y, sprite_type });
+    }
+
+    pub fn remove_sprite(&mut self, level: &mut Level, index: usize) {
+        let sprite = &level.sprites[index];
+        let command = EditCommand::RemoveSprite {
+            x: sprite.pos.x,
+            y: sprite.pos.y,
+            sprite_type: sprite.sprite_type,
+        };
+        self.execute(level, command);
+    }
+
+    pub fn move_spawn(&mut self, level: &mut Level, x: f64, y: f64) {
+        let old = (level.spawnx, level.spawny);
+        if old == (x, y) {
+            return;
+        }
+
+        self.execute(level, EditCommand::MoveSpawn { old, new: (x, y) });
+    }
+
+    pub fn undo(&mut self, level: &mut Level) {
+        self.end_group();
+
+        if let Some(commands) = self.undo_stack.pop() {
+            for command in commands.iter().rev() {
+                command.inverse().apply(level);
+            }
+            self.redo_stack.push(commands);
+        }
+    }
+
+    pub fn redo(&mut self, level: &mut Level) {
+        self.end_group();
+
+        if let Some(commands) = self.redo_stack.pop() {
+            for command in &commands {
+                command.apply(level);
+            }
+            self.undo_stack.push(commands);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn group_is_undone_and_redone_in_one_step() {
+        let mut level = Level::new(4, 1);
+        let mut history = EditHistory::new();
+
+        history.set_tile(&mut level, TileLayer::Walls, 0, 0, 1);
+        history.begin_group();
+        for x in 1..4 {
+            history.set_tile(&mut level, TileLayer::Walls, x, 0, 2);
+        }
+        history.end_group();
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [1, 2, 2, 2]);
+
+        history.undo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [1, 0, 0, 0]);
+        history.redo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [1, 2, 2, 2]);
+        history.undo(&mut level);
+        history.undo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn new_edit_clears_redo() {
+        let mut level = Level::new(2, 1);
+        let mut history = EditHistory::new();
+
+        history.set_tile(&mut level, TileLayer::Walls, 0, 0, 1);
+        history.undo(&mut level);
+        history.set_tile(&mut level, TileLayer::Walls, 1, 0, 3);
+        history.redo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 3]);
+
+        history.undo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 0]);
+    }
+
+    #[test]
+    fn undo_in_the_middle_of_a_group_ends_it() {
+        let mut level = Level::new(3, 1);
+        let mut history = EditHistory::new();
+
+        history.begin_group();
+        history.set_tile(&mut level, TileLayer::Walls, 0, 0, 1);
+        history.set_tile(&mut level, TileLayer::Walls, 1, 0, 1);
+        history.undo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 0, 0]);
+
+        // The group was closed by the undo, so it comes back in one step
+        // and later edits aren't added to it
+        history.redo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [1, 1, 0]);
+        history.set_tile(&mut level, TileLayer::Walls, 2, 0, 1);
+        history.undo(&mut level);
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [1, 1, 0]);
+    }
+}
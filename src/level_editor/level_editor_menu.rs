@@ -1,11 +1,13 @@
+use crate::animation::SpriteDef;
+use crate::framebuffer::TextureData;
+use crate::level::{TileLayer, HEIGHT_LEVELS, LIGHT_LEVELS, OPEN_SKY};
+use crate::manifest::Assets;
 use crate::menu::{MenuElement, Text};
 use crate::InputState;
-use sdl2::image::LoadTexture;
 use sdl2::mouse::MouseButton;
-use sdl2::pixels::Color;
+use sdl2::pixels::{Color, PixelFormatEnum};
 use sdl2::rect::{Point, Rect};
-use sdl2::render::Texture;
-use sdl2::render::{Canvas, TextureCreator};
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
 use sdl2::ttf::Font;
 use sdl2::video::{Window, WindowContext};
 
@@ -14,11 +16,24 @@ pub enum EditorMode {
     Sprites,
 }
 
+// Where the texture/sprite icons start, relative to the top of the menu
+const ICONS_TOP: i32 = 172;
+// Ids of the buttons that pick which layer of tiles gets painted
+const LAYER_BUTTONS: [(&str, TileLayer); 5] = [
+    ("walls_button", TileLayer::Walls),
+    ("floor_button", TileLayer::Floor),
+    ("ceiling_button", TileLayer::Ceiling),
+    ("light_button", TileLayer::Light),
+    ("height_button", TileLayer::Height),
+];
+
 pub struct LevelEditorMenu {
     pub menu: MenuElement,
     pub selected: u8,
     pub icon_sz: u32,
     pub editor_mode: EditorMode,
+    // Layer that tiles are painted onto
+    pub layer: TileLayer,
 }
 
 impl LevelEditorMenu {
@@ -126,25 +141,117 @@ impl LevelEditorMenu {
             level_editor_menu.children.push(sprite_button);
         }
 
+        // Wall/Floor/Ceiling/Light layer buttons with the Height
+        // button on a second row underneath them
+        for (x, y, w, label, (id, _)) in [
+            (16 + 32, 16 + 48 + 40, 64, "Walls", LAYER_BUTTONS[0]),
+            (
+                16 + 64 + 4 + 32,
+                16 + 48 + 40,
+                64,
+                "Floor",
+                LAYER_BUTTONS[1],
+            ),
+            (
+                16 + 64 + 4 + 64 + 4 + 40,
+                16 + 48 + 40,
+                80,
+                "Ceiling",
+                LAYER_BUTTONS[2],
+            ),
+            (
+                16 + 64 + 4 + 64 + 4 + 80 + 4 + 32,
+                16 + 48 + 40,
+                64,
+                "Light",
+                LAYER_BUTTONS[3],
+            ),
+            (16 + 40, 16 + 48 + 40 + 36, 80, "Height", LAYER_BUTTONS[4]),
+        ] {
+            let mut layer_button =
+                MenuElement::new(x, y, w, 32, Color::RGB(48, 48, 48), Color::RGB(64, 64, 64));
+
+            layer_button
+                .text
+                .push(Text::new(label, Color::WHITE, 8, 6, 10));
+            layer_button.set_id(id);
+
+            level_editor_menu.children.push(layer_button);
+        }
+
         level_editor_menu
             .text
             .push(Text::new("Level Editor", Color::WHITE, 16, 16, 12));
 
-        Self {
+        let mut menu = Self {
             menu: level_editor_menu,
             selected: 1,
             icon_sz: 32,
             editor_mode: EditorMode::Tiles,
+            layer: TileLayer::Walls,
+        };
+        menu.set_layer(TileLayer::Walls);
+        menu
+    }
+
+    // Keeps the menu on the right side of the window and
+    // the buttons at the bottom of the menu at the bottom
+    pub fn resize(&mut self, window_w: u32, window_h: u32) {
+        let width = self.menu.width();
+        self.menu.set_bounds(
+            window_w as i32 - width as i32 / 2,
+            window_h as i32 / 2,
+            width,
+            window_h,
+        );
+
+        let bottom = window_h as i32;
+        for child in &mut self.menu.children {
+            let y = match child.id.as_deref() {
+                Some("play_button") => bottom - 16 - 16,
+                Some("save_button") | Some("load_button") => bottom - 16 - 16 - 24 - 16,
+                _ => continue,
+            };
+
+            let x = child.x() + child.width() as i32 / 2;
+            child.set_bounds(x, y, child.width(), child.height());
         }
     }
 
+    // Returns the layer for a layer button id
+    pub fn layer_for_button(id: &str) -> Option<TileLayer> {
+        LAYER_BUTTONS
+            .iter()
+            .find(|(button_id, _)| *button_id == id)
+            .map(|(_, layer)| *layer)
+    }
+
+    // Switches the layer that gets painted and highlights its button
+    pub fn set_layer(&mut self, layer: TileLayer) {
+        self.layer = layer;
+
+        for child in &mut self.menu.children {
+            let button_layer = child.id.as_deref().and_then(Self::layer_for_button);
+            if let Some(button_layer) = button_layer {
+                child.normal_color = if button_layer == layer {
+                    Color::RGB(80, 80, 112)
+                } else {
+                    Color::RGB(48, 48, 48)
+                };
+            }
+        }
+    }
+
+    // `icons` are the id that each icon picks and its image, `frames`
+    // are indexed by id - 1
     pub fn display(
         &self,
         canvas: &mut Canvas<Window>,
         input_state: &InputState,
         texture_creator: &TextureCreator<WindowContext>,
         font: &Font,
-        textures: &[Texture],
+        icons: &[(u8, &Texture)],
+        frames: &[Rect],
     ) -> Result<(), String> {
         self.menu.display_with_children(canvas, input_state)?;
         self.menu
@@ -153,8 +260,8 @@ impl LevelEditorMenu {
         let (mousex, mousey) = input_state.mouse_pos();
 
         let mut x = 16;
-        let mut y = 96;
-        for (i, texture) in textures.iter().enumerate() {
+        let mut y = ICONS_TOP;
+        for (id, texture) in icons {
             let icon_rect = Rect::new(
                 x + self.menu.x(),
                 y + self.menu.y(),
@@ -162,9 +269,10 @@ impl LevelEditorMenu {
                 self.icon_sz,
             );
 
-            canvas.copy(texture, None, icon_rect)?;
+            // Only part of the image is shown for sprite sheets
+            canvas.copy(texture, frames.get(*id as usize - 1).copied(), icon_rect)?;
 
-            if i + 1 == self.selected as usize {
+            if *id == self.selected {
                 canvas.set_draw_color(Color::WHITE);
                 canvas.draw_rect(icon_rect)?;
             }
@@ -184,12 +292,19 @@ impl LevelEditorMenu {
         Ok(())
     }
 
-    pub fn handle_mouse_input(&mut self, input_state: &InputState, tile_count: u8) {
+    pub fn handle_mouse_input(&mut self, input_state: &InputState, icons: &[(u8, &Texture)]) {
+        // Keep the selection on something that is in the palette
+        if !icons.iter().any(|(id, _)| *id == self.selected) {
+            if let Some((id, _)) = icons.first() {
+                self.selected = *id;
+            }
+        }
+
         let mut x = 16;
-        let mut y = 96;
+        let mut y = ICONS_TOP;
 
         let (mousex, mousey) = input_state.mouse_pos();
-        for tile in 0..tile_count {
+        for (id, _) in icons {
             let icon_rect = Rect::new(
                 x + self.menu.x(),
                 y + self.menu.y(),
@@ -200,7 +315,7 @@ impl LevelEditorMenu {
             if icon_rect.contains_point(Point::new(mousex, mousey))
                 && input_state.mouse_button_is_clicked(MouseButton::Left)
             {
-                self.selected = tile + 1;
+                self.selected = *id;
                 return;
             }
 
@@ -213,62 +328,145 @@ impl LevelEditorMenu {
     }
 }
 
-pub fn load_default_assets(texture_creator: &TextureCreator<WindowContext>) -> Vec<Texture> {
-    let default_texture_path = vec![
-        "assets/images/textures/test-texture.png",
-        "assets/images/textures/bricks.png",
-        "assets/images/textures/wall1.png",
-        "assets/images/textures/wall2.png",
-        "assets/images/textures/wall3.png",
-        "assets/images/textures/wall4.png",
-        "assets/images/textures/whitewall.png",
-        "assets/images/textures/box.png",
-        "assets/images/textures/stripeblock.png",
-        "assets/images/textures/red_door.png",
-        "assets/images/textures/blue_door.png",
-        "assets/images/textures/green_door.png",
-    ];
-
-    let mut textures = vec![];
-
-    for path in default_texture_path {
-        let res = texture_creator.load_texture(path);
-
-        match res {
-            Ok(tex) => textures.push(tex),
-            Err(msg) => {
-                eprintln!("{msg}")
-            }
-        }
-    }
+// Uploads images to the GPU so that the editor can draw them
+fn create_textures<'a>(
+    texture_creator: &'a TextureCreator<WindowContext>,
+    images: &[TextureData],
+) -> Result<Vec<Texture<'a>>, String> {
+    images
+        .iter()
+        .map(|image| {
+            let mut texture = texture_creator
+                .create_texture_static(PixelFormatEnum::RGBA32, image.width(), image.height())
+                .map_err(|e| e.to_string())?;
+            texture
+                .update(None, image.pixels(), image.width() as usize * 4)
+                .map_err(|e| e.to_string())?;
+            texture.set_blend_mode(BlendMode::Blend);
+            Ok(texture)
+        })
+        .collect()
+}
 
-    textures
+// Textures that the editor draws the level and the menu with
+pub struct EditorTextures<'a> {
+    pub textures: Vec<Texture<'a>>,
+    pub sprite_images: Vec<Texture<'a>>,
+    // Part of each sprite's image that is shown in the editor
+    pub sprite_frames: Vec<Rect>,
+    // Ids that are in the manifest, the skies are left out of the tiles
+    tile_ids: Vec<u8>,
+    sprite_ids: Vec<u8>,
+    // Shown for ceiling tiles that are open sky
+    pub open_sky: Texture<'a>,
 }
 
-pub fn load_default_sprites(texture_creator: &TextureCreator<WindowContext>) -> Vec<Texture> {
-    let sprite_texture_path = vec![
-        "assets/images/sprites/red_key.png",
-        "assets/images/sprites/blue_key.png",
-        "assets/images/sprites/green_key.png",
-        "assets/images/sprites/alien1.png",
-        "assets/images/sprites/alien2.png",
-        "assets/images/sprites/explosive_barrel.png",
-        "assets/images/sprites/health.png",
-        "assets/images/sprites/bullets.png",
-    ];
-
-    let mut textures = vec![];
-
-    for path in sprite_texture_path {
-        let res = texture_creator.load_texture(path);
-
-        match res {
-            Ok(tex) => textures.push(tex),
-            Err(msg) => {
-                eprintln!("{msg}")
-            }
+impl<'a> EditorTextures<'a> {
+    pub fn new(
+        texture_creator: &'a TextureCreator<WindowContext>,
+        assets: &Assets,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            textures: create_textures(texture_creator, &assets.textures)?,
+            sprite_images: create_textures(texture_creator, &assets.sprites)?,
+            sprite_frames: assets
+                .sprite_defs
+                .iter()
+                .map(SpriteDef::icon_rect)
+                .collect(),
+            tile_ids: assets.manifest.tile_ids(),
+            sprite_ids: assets.manifest.sprite_ids(),
+            open_sky: create_sky_swatch(texture_creator)?,
+        })
+    }
+
+    // Icons for the textures that can be painted onto a layer,
+    // the ceiling can also be painted with open sky
+    pub fn tile_icons(&self, layer: TileLayer) -> Vec<(u8, &Texture<'a>)> {
+        let mut icons: Vec<_> = self
+            .tile_ids
+            .iter()
+            .filter(|id| layer != TileLayer::Ceiling || **id != OPEN_SKY)
+            .map(|id| (*id, &self.textures[*id as usize - 1]))
+            .collect();
+        if layer == TileLayer::Ceiling {
+            icons.push((OPEN_SKY, &self.open_sky));
         }
+        icons
     }
 
-    textures
+    pub fn sprite_icons(&self) -> Vec<(u8, &Texture<'a>)> {
+        self.sprite_ids
+            .iter()
+            .map(|id| (*id, &self.sprite_images[*id as usize - 1]))
+            .collect()
+    }
+}
+
+// Icons for a list of textures, the first one has id 1
+pub fn numbered_icons<'a, 'b>(textures: &'b [Texture<'a>]) -> Vec<(u8, &'b Texture<'a>)> {
+    (1..).zip(textures).collect()
+}
+
+// Blue that gets paler towards the bottom, the icon for open sky
+fn create_sky_swatch(
+    texture_creator: &TextureCreator<WindowContext>,
+) -> Result<Texture<'_>, String> {
+    let size = 8;
+    let mut swatch = texture_creator
+        .create_texture_streaming(PixelFormatEnum::RGBA32, 1, size)
+        .map_err(|e| e.to_string())?;
+    let pixels: Vec<u8> = (0..size)
+        .flat_map(|y| {
+            let fade = (y * 128 / size) as u8;
+            [96 + fade, 160 + fade / 2, 255, 255]
+        })
+        .collect();
+    swatch.update(None, &pixels, 4).map_err(|e| e.to_string())?;
+    Ok(swatch)
+}
+
+// Gray squares used as the icons for the light levels, the
+// first one is light level 1 (light level 0 is fully lit)
+pub fn create_light_swatches(
+    texture_creator: &TextureCreator<WindowContext>,
+) -> Result<Vec<Texture<'_>>, String> {
+    (1..LIGHT_LEVELS)
+        .map(|darkness| {
+            let mut swatch = texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGBA32, 1, 1)
+                .map_err(|e| e.to_string())?;
+            let value = (255 - darkness as u32 * 255 / LIGHT_LEVELS as u32) as u8;
+            swatch
+                .update(None, &[value, value, value, 255], 4)
+                .map_err(|e| e.to_string())?;
+            Ok(swatch)
+        })
+        .collect()
+}
+
+// Bars that get taller with the wall height that they are the icon
+// for, the first one is a quarter of a tile tall (0 is the normal height)
+pub fn create_height_swatches(
+    texture_creator: &TextureCreator<WindowContext>,
+) -> Result<Vec<Texture<'_>>, String> {
+    let size = HEIGHT_LEVELS as u32 - 1;
+    (1..HEIGHT_LEVELS)
+        .map(|quarters| {
+            let mut swatch = texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGBA32, size, size)
+                .map_err(|e| e.to_string())?;
+            let pixels: Vec<u8> = (0..size)
+                .flat_map(|y| {
+                    let filled = y >= size - quarters as u32;
+                    let value = if filled { 224 } else { 48 };
+                    [value, value, value, 255].repeat(size as usize)
+                })
+                .collect();
+            swatch
+                .update(None, &pixels, size as usize * 4)
+                .map_err(|e| e.to_string())?;
+            Ok(swatch)
+        })
+        .collect()
 }
@@ -1,130 +1,995 @@
-use crate::{sprite::Sprite, Level};
+use crate::{
+    level::{TileLayer, HEIGHT_LEVELS, LIGHT_LEVELS},
+    level_editor::level_text_file::{level_to_text, parse_level_text},
+    sprite::Sprite,
+    Level,
+};
 use std::{
+    fmt,
     fs::File,
     io::{Read, Write},
+    path::Path,
 };
 
-pub fn write_level_file(level: &Level, path: &str) -> Result<(), String> {
-    let mut level_file = File::create(path).map_err(|e| e.to_string())?;
-    // Write the width and height as bytes to the file
-    level_file
-        .write(&level.width.to_be_bytes())
-        .map_err(|e| e.to_string())?;
-    level_file
-        .write(&level.height.to_be_bytes())
-        .map_err(|e| e.to_string())?;
-    // Write the starting position of the player
-    level_file
-        .write(&level.spawnx.to_be_bytes())
-        .map_err(|e| e.to_string())?;
-    level_file
-        .write(&level.spawny.to_be_bytes())
-        .map_err(|e| e.to_string())?;
-    // Write the level data to the file
-    level_file
-        .write_all(level.level_data_bytes())
-        .map_err(|e| e.to_string())?;
-    //Write the sprites to the file
-    level_file
-        .write(&(level.sprites.len() as u32).to_be_bytes())
-        .map_err(|e| e.to_string())?;
+// Level files start with this so that we can tell them apart from
+// files made before the format had a header (version 0)
+const MAGIC: [u8; 4] = *b"RFPL";
+// Version of the file layout (header + list of sections)
+const FORMAT_VERSION: u16 = 1;
 
-    for sprite in &level.sprites {
-        level_file
-            .write(&sprite.pos.x.to_be_bytes())
-            .map_err(|e| e.to_string())?;
-        level_file
-            .write(&sprite.pos.y.to_be_bytes())
-            .map_err(|e| e.to_string())?;
-        level_file
-            .write(&sprite.sprite_type.to_be_bytes())
-            .map_err(|e| e.to_string())?;
+// Every section is written as a 4 byte tag, a u16 version and a u32 length
+// followed by `length` bytes of data. New data (sprite properties,
+// metadata, etc.) should be added as a new section or a new version of an
+// existing section, readers skip over any section they do not know about.
+const TILES_SECTION: [u8; 4] = *b"TILE";
+const TILES_SECTION_VERSION: u16 = 1;
+const SPRITES_SECTION: [u8; 4] = *b"SPRT";
+const SPRITES_SECTION_VERSION: u16 = 1;
+// The floor and ceiling sections are the untextured color (r, g, b)
+// followed by one texture per tile, the light section is one light
+// level per tile and the height section is one wall height (in quarters
+// of a tile, 0 for the normal height) per tile. They have to come after
+// the tile section.
+const FLOOR_SECTION: [u8; 4] = *b"FLOR";
+const CEILING_SECTION: [u8; 4] = *b"CEIL";
+const LIGHT_SECTION: [u8; 4] = *b"LITE";
+const HEIGHT_SECTION: [u8; 4] = *b"HGHT";
+// Tag, layer, name and version of each of the layer sections
+const LAYER_SECTIONS: [([u8; 4], TileLayer, &str, u16); 4] = [
+    (FLOOR_SECTION, TileLayer::Floor, "floor", 1),
+    (CEILING_SECTION, TileLayer::Ceiling, "ceiling", 1),
+    (LIGHT_SECTION, TileLayer::Light, "light", 1),
+    (HEIGHT_SECTION, TileLayer::Height, "height", 1),
+];
+// Fog color (r, g, b) and the distance (f64) at which the fog is solid
+const FOG_SECTION: [u8; 4] = *b"FOG ";
+const FOG_SECTION_VERSION: u16 = 1;
+// Path of the level's own manifest as UTF-8, only written for levels that have one
+const MANIFEST_SECTION: [u8; 4] = *b"MANI";
+const MANIFEST_SECTION_VERSION: u16 = 1;
+// Texture id (u8) of the sky, only written for levels that have one
+const SKY_SECTION: [u8; 4] = *b"SKY ";
+const SKY_SECTION_VERSION: u16 = 1;
+
+// Largest width or height a level can have, this stops a bad
+// header from making us allocate a huge amount of memory
+pub const MAX_LEVEL_DIMENSION: u32 = 1024;
+// Each sprite is stored as x (f64), y (f64) and the type (u8)
+const SPRITE_SIZE: usize = 17;
+
+#[derive(Debug)]
+pub enum LevelFileError {
+    Io(std::io::Error),
+    // The file ended before the named section was fully read
+    TruncatedSection(&'static str),
+    UnsupportedVersion(u16),
+    UnsupportedSectionVersion { section: &'static str, version: u16 },
+    MissingSection(&'static str),
+    // A section was found before a section that it depends on
+    SectionOutOfOrder(&'static str),
+    // A section that can only be in a file once was found again
+    DuplicateSection(&'static str),
+    DimensionsTooLarge { width: u32, height: u32 },
+    InvalidTileType(u8),
+    InvalidSpriteType(u8),
+    InvalidLightLevel(u8),
+    InvalidWallHeight(u8),
+    InvalidSkyTexture(u8),
+    // A fog distance that is negative, NaN or infinite
+    InvalidFogDistance,
+    // A position that is NaN or infinite
+    InvalidPosition(&'static str),
+    InvalidManifestPath,
+    // Number of extra bytes found after the end of a section or the file
+    TrailingData { section: &'static str, bytes: usize },
+    // A line in a text level file that could not be understood
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for LevelFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::TruncatedSection(section) => {
+                write!(f, "Level file ended in the middle of the {section} section")
+            }
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported level file version: {version}")
+            }
+            Self::UnsupportedSectionVersion { section, version } => {
+                write!(f, "Unsupported {section} section version: {version}")
+            }
+            Self::MissingSection(section) => {
+                write!(f, "Level file has no {section} section")
+            }
+            Self::SectionOutOfOrder(section) => {
+                write!(f, "The {section} section was found before the tile section")
+            }
+            Self::DuplicateSection(section) => {
+                write!(f, "The {section} section appears more than once")
+            }
+            Self::DimensionsTooLarge { width, height } => write!(
+                f,
+                "Level is {width}x{height}, the largest allowed is \
+                {MAX_LEVEL_DIMENSION}x{MAX_LEVEL_DIMENSION}"
+            ),
+            Self::InvalidTileType(tile) => write!(f, "Invalid tile type: {tile}"),
+            Self::InvalidSpriteType(sprite_type) => {
+                write!(f, "Invalid sprite type: {sprite_type}")
+            }
+            Self::InvalidLightLevel(light) => write!(
+                f,
+                "Invalid light level: {light}, the highest is {}",
+                LIGHT_LEVELS - 1
+            ),
+            Self::InvalidWallHeight(height) => write!(
+                f,
+                "Invalid wall height: {height}, the highest is {}",
+                HEIGHT_LEVELS - 1
+            ),
+            Self::InvalidSkyTexture(sky) => write!(f, "Invalid sky texture: {sky}"),
+            Self::InvalidFogDistance => write!(f, "Invalid fog distance"),
+            Self::InvalidPosition(what) => write!(f, "Invalid {what} position"),
+            Self::InvalidManifestPath => write!(f, "Manifest path is not valid UTF-8"),
+            Self::TrailingData { section, bytes } => {
+                write!(f, "{bytes} unexpected bytes after the {section} section")
+            }
+            Self::Parse { line, message } => write!(f, "Line {line}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for LevelFileError {}
+
+impl From<std::io::Error> for LevelFileError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+// Levels can either be saved as binary or as text,
+// which one is used depends on the file extension
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LevelFormat {
+    Binary,
+    Text,
+}
+
+impl LevelFormat {
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("txt") => Self::Text,
+            _ => Self::Binary,
+        }
+    }
+}
+
+// Checks shared by the binary and text formats
+pub fn check_dimensions(width: u32, height: u32) -> Result<(), LevelFileError> {
+    if width > MAX_LEVEL_DIMENSION || height > MAX_LEVEL_DIMENSION {
+        return Err(LevelFileError::DimensionsTooLarge { width, height });
     }
 
     Ok(())
 }
 
-pub fn read_level_file(path: &str) -> Result<Level, String> {
-    let mut level_file = File::open(path).map_err(|e| e.to_string())?;
-
-    let mut level = {
-        // read the dimensions from the file
-        let mut width = [0u8; std::mem::size_of::<u32>()];
-        level_file
-            .read_exact(&mut width)
-            .map_err(|e| e.to_string())?;
-        let mut height = [0u8; std::mem::size_of::<u32>()];
-        level_file
-            .read_exact(&mut height)
-            .map_err(|e| e.to_string())?;
-
-        // read the starting position from the file
-        let mut startx = [0u8; std::mem::size_of::<f64>()];
-        level_file
-            .read_exact(&mut startx)
-            .map_err(|e| e.to_string())?;
-        let mut starty = [0u8; std::mem::size_of::<f64>()];
-        level_file
-            .read_exact(&mut starty)
-            .map_err(|e| e.to_string())?;
-
-        let mut empty_level = Level::new(u32::from_be_bytes(width), u32::from_be_bytes(height));
-        empty_level.spawnx = f64::from_be_bytes(startx);
-        empty_level.spawny = f64::from_be_bytes(starty);
-
-        empty_level
+pub fn check_position(x: f64, y: f64, what: &'static str) -> Result<(), LevelFileError> {
+    if !x.is_finite() || !y.is_finite() {
+        return Err(LevelFileError::InvalidPosition(what));
+    }
+
+    Ok(())
+}
+
+pub fn check_sprite_type(sprite_type: u8) -> Result<(), LevelFileError> {
+    if sprite_type == 0 {
+        return Err(LevelFileError::InvalidSpriteType(sprite_type));
+    }
+
+    Ok(())
+}
+
+// Textures and sprites are checked once the level's manifest has been loaded
+pub fn check_layer_tile(layer: TileLayer, tile: u8) -> Result<(), LevelFileError> {
+    if layer == TileLayer::Light && tile >= LIGHT_LEVELS {
+        return Err(LevelFileError::InvalidLightLevel(tile));
+    }
+    if layer == TileLayer::Height && tile >= HEIGHT_LEVELS {
+        return Err(LevelFileError::InvalidWallHeight(tile));
+    }
+
+    Ok(())
+}
+
+pub fn check_fog_distance(distance: f64) -> Result<(), LevelFileError> {
+    if !distance.is_finite() || distance < 0.0 {
+        return Err(LevelFileError::InvalidFogDistance);
+    }
+
+    Ok(())
+}
+
+// Sprites outside of the level are dropped when loading
+pub fn place_loaded_sprite(level: &mut Level, sprite: Sprite) {
+    if sprite.pos.x as u32 > level.width
+        || sprite.pos.y as u32 > level.height
+        || sprite.pos.x < 0.0
+        || sprite.pos.y < 0.0
+    {
+        return;
+    }
+
+    level.place_sprite(sprite);
+}
+
+// Reads big endian values out of a buffer, `section` is the
+// name of the section that is being read for error messages
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    section: &'static str,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8], section: &'static str) -> Self {
+        Self {
+            bytes,
+            position: 0,
+            section,
+        }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], LevelFileError> {
+        if count > self.remaining() {
+            return Err(LevelFileError::TruncatedSection(self.section));
+        }
+
+        let bytes = &self.bytes[self.position..(self.position + count)];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], LevelFileError> {
+        let mut bytes = [0u8; N];
+        bytes.copy_from_slice(self.read_bytes(N)?);
+        Ok(bytes)
+    }
+
+    fn read_u8(&mut self) -> Result<u8, LevelFileError> {
+        Ok(u8::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u16(&mut self) -> Result<u16, LevelFileError> {
+        Ok(u16::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_u32(&mut self) -> Result<u32, LevelFileError> {
+        Ok(u32::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_f64(&mut self) -> Result<f64, LevelFileError> {
+        Ok(f64::from_be_bytes(self.read_array()?))
+    }
+
+    fn read_position(&mut self, what: &'static str) -> Result<(f64, f64), LevelFileError> {
+        let x = self.read_f64()?;
+        let y = self.read_f64()?;
+        check_position(x, y, what)?;
+        Ok((x, y))
+    }
+
+    // Makes sure that everything in the section was read
+    fn finish(&self) -> Result<(), LevelFileError> {
+        if self.remaining() > 0 {
+            return Err(LevelFileError::TrailingData {
+                section: self.section,
+                bytes: self.remaining(),
+            });
+        }
+
+        Ok(())
+    }
+}
+
+fn write_section(out: &mut Vec<u8>, tag: &[u8; 4], version: u16, data: &[u8]) {
+    out.extend_from_slice(tag);
+    out.extend_from_slice(&version.to_be_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn tiles_section(level: &Level) -> Vec<u8> {
+    let mut data = vec![];
+    // Width and height of the level
+    data.extend_from_slice(&level.width.to_be_bytes());
+    data.extend_from_slice(&level.height.to_be_bytes());
+    // Starting position of the player
+    data.extend_from_slice(&level.spawnx.to_be_bytes());
+    data.extend_from_slice(&level.spawny.to_be_bytes());
+    // The tiles themselves
+    data.extend_from_slice(level.layer_bytes(TileLayer::Walls));
+    data
+}
+
+fn sprites_section(level: &Level) -> Vec<u8> {
+    let mut data = vec![];
+    data.extend_from_slice(&(level.sprites.len() as u32).to_be_bytes());
+
+    for sprite in &level.sprites {
+        data.extend_from_slice(&sprite.pos.x.to_be_bytes());
+        data.extend_from_slice(&sprite.pos.y.to_be_bytes());
+        data.extend_from_slice(&sprite.sprite_type.to_be_bytes());
+    }
+
+    data
+}
+
+// The floor and ceiling have a color that is used for untextured tiles
+fn untextured_color(level: &mut Level, layer: TileLayer) -> Option<&mut [u8; 3]> {
+    match layer {
+        TileLayer::Floor => Some(&mut level.floor_color),
+        TileLayer::Ceiling => Some(&mut level.ceiling_color),
+        _ => None,
+    }
+}
+
+fn layer_section(level: &Level, layer: TileLayer) -> Vec<u8> {
+    let mut data = match layer {
+        TileLayer::Floor => level.floor_color.to_vec(),
+        TileLayer::Ceiling => level.ceiling_color.to_vec(),
+        _ => vec![],
+    };
+    data.extend_from_slice(level.layer_bytes(layer));
+    data
+}
+
+fn fog_section(level: &Level) -> Vec<u8> {
+    let mut data = level.fog_color.to_vec();
+    data.extend_from_slice(&level.fog_distance.to_be_bytes());
+    data
+}
+
+fn level_to_bytes(level: &Level) -> Vec<u8> {
+    let mut bytes = vec![];
+    bytes.extend_from_slice(&MAGIC);
+    bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    write_section(
+        &mut bytes,
+        &TILES_SECTION,
+        TILES_SECTION_VERSION,
+        &tiles_section(level),
+    );
+    write_section(
+        &mut bytes,
+        &SPRITES_SECTION,
+        SPRITES_SECTION_VERSION,
+        &sprites_section(level),
+    );
+    for (tag, layer, _, version) in LAYER_SECTIONS {
+        write_section(&mut bytes, &tag, version, &layer_section(level, layer));
+    }
+    write_section(
+        &mut bytes,
+        &FOG_SECTION,
+        FOG_SECTION_VERSION,
+        &fog_section(level),
+    );
+    if let Some(manifest) = &level.manifest {
+        write_section(
+            &mut bytes,
+            &MANIFEST_SECTION,
+            MANIFEST_SECTION_VERSION,
+            manifest.as_bytes(),
+        );
+    }
+    if level.sky != 0 {
+        write_section(&mut bytes, &SKY_SECTION, SKY_SECTION_VERSION, &[level.sky]);
+    }
+    bytes
+}
+
+pub fn write_level_file(level: &Level, path: &str) -> Result<(), LevelFileError> {
+    let bytes = match LevelFormat::from_path(path) {
+        LevelFormat::Binary => level_to_bytes(level),
+        LevelFormat::Text => level_to_text(level).into_bytes(),
     };
 
-    let mut level_data = vec![0u8; (level.width * level.height) as usize];
-    level_file
-        .read(&mut level_data)
-        .map_err(|e| e.to_string())?;
+    let mut level_file = File::create(path)?;
+    level_file.write_all(&bytes)?;
+
+    Ok(())
+}
+
+fn read_tiles(reader: &mut ByteReader) -> Result<Level, LevelFileError> {
+    // read the dimensions
+    let width = reader.read_u32()?;
+    let height = reader.read_u32()?;
+
+    check_dimensions(width, height)?;
+
+    // read the starting position
+    let (spawnx, spawny) = reader.read_position("spawn")?;
+
+    // Read the tiles before creating the level so that a truncated
+    // file does not allocate anything
+    let level_data = reader.read_bytes(width as usize * height as usize)?;
+
+    let mut level = Level::new(width, height);
+    level.spawnx = spawnx;
+    level.spawny = spawny;
 
     for (i, tile) in level_data.iter().enumerate() {
         let (x, y) = (i % (level.width as usize), i / (level.width as usize));
         level.set_tile(x as isize, y as isize, *tile);
     }
 
-    let sprite_count = {
-        let mut sprite_count_bytes = [0u8; std::mem::size_of::<u32>()];
-        level_file
-            .read_exact(&mut sprite_count_bytes)
-            .map_err(|e| e.to_string())?;
-        u32::from_be_bytes(sprite_count_bytes)
-    };
+    Ok(level)
+}
+
+fn read_sprites(reader: &mut ByteReader, level: &mut Level) -> Result<(), LevelFileError> {
+    let sprite_count = reader.read_u32()?;
+
+    if sprite_count as usize > reader.remaining() / SPRITE_SIZE {
+        return Err(LevelFileError::TruncatedSection(reader.section));
+    }
 
     for _ in 0..sprite_count {
         let sprite = {
-            let mut sprite_x = [0u8; std::mem::size_of::<f64>()];
-            level_file
-                .read_exact(&mut sprite_x)
-                .map_err(|e| e.to_string())?;
-            let mut sprite_y = [0u8; std::mem::size_of::<f64>()];
-            level_file
-                .read_exact(&mut sprite_y)
-                .map_err(|e| e.to_string())?;
-            let mut sprite_type = [0u8; std::mem::size_of::<u8>()];
-            level_file
-                .read_exact(&mut sprite_type)
-                .map_err(|e| e.to_string())?;
-
-            Sprite::new(
-                f64::from_be_bytes(sprite_x),
-                f64::from_be_bytes(sprite_y),
-                u8::from_be_bytes(sprite_type),
-            )
+            let (sprite_x, sprite_y) = reader.read_position("sprite")?;
+            let sprite_type = reader.read_u8()?;
+
+            check_sprite_type(sprite_type)?;
+            Sprite::new(sprite_x, sprite_y, sprite_type)
         };
 
-        if sprite.pos.x as u32 > level.width
-            || sprite.pos.y as u32 > level.height
-            || sprite.pos.x < 0.0
-            || sprite.pos.y < 0.0
-        {
-            continue;
-        }
+        place_loaded_sprite(level, sprite);
+    }
+
+    Ok(())
+}
 
-        level.place_sprite(sprite);
+fn read_layer(
+    reader: &mut ByteReader,
+    level: &mut Level,
+    layer: TileLayer,
+) -> Result<(), LevelFileError> {
+    if let Some(color) = untextured_color(level, layer) {
+        *color = reader.read_array::<3>()?;
+    }
+
+    let tiles = reader.read_bytes(level.width as usize * level.height as usize)?;
+    for (i, tile) in tiles.iter().enumerate() {
+        check_layer_tile(layer, *tile)?;
+        let (x, y) = (i % (level.width as usize), i / (level.width as usize));
+        level.set_layer_tile(layer, x as isize, y as isize, *tile);
     }
 
+    Ok(())
+}
+
+fn read_fog(reader: &mut ByteReader, level: &mut Level) -> Result<(), LevelFileError> {
+    let color = reader.read_array::<3>()?;
+    let distance = reader.read_f64()?;
+    check_fog_distance(distance)?;
+
+    level.fog_color = color;
+    level.fog_distance = distance;
+    Ok(())
+}
+
+// Files from before the header was added are just the tiles
+// followed by the sprites, with no tags or lengths
+fn read_v0(bytes: &[u8]) -> Result<Level, LevelFileError> {
+    let mut reader = ByteReader::new(bytes, "tile");
+    let mut level = read_tiles(&mut reader)?;
+    reader.section = "sprite";
+    read_sprites(&mut reader, &mut level)?;
+    reader.finish()?;
     Ok(level)
 }
+
+fn section_name(tag: &[u8]) -> &'static str {
+    if tag == TILES_SECTION {
+        "tile"
+    } else if tag == SPRITES_SECTION {
+        "sprite"
+    } else if tag == FOG_SECTION {
+        "fog"
+    } else if tag == MANIFEST_SECTION {
+        "manifest"
+    } else if tag == SKY_SECTION {
+        "sky"
+    } else {
+        LAYER_SECTIONS
+            .iter()
+            .find(|(layer_tag, ..)| layer_tag == tag)
+            .map(|(_, _, name, _)| *name)
+            .unwrap_or("unknown")
+    }
+}
+
+fn read_v1(reader: &mut ByteReader) -> Result<Level, LevelFileError> {
+    let mut level = None;
+    // Tags of the sections that have been read, unknown ones aren't kept
+    let mut seen_tags: Vec<&[u8]> = vec![];
+
+    while reader.remaining() > 0 {
+        reader.section = "section header";
+        let tag = reader.read_bytes(4)?;
+        reader.section = section_name(tag);
+        let version = reader.read_u16()?;
+        let length = reader.read_u32()? as usize;
+        let section_bytes = reader.read_bytes(length)?;
+
+        if section_name(tag) != "unknown" {
+            if seen_tags.contains(&tag) {
+                return Err(LevelFileError::DuplicateSection(section_name(tag)));
+            }
+            seen_tags.push(tag);
+        }
+
+        let layer_section = LAYER_SECTIONS
+            .into_iter()
+            .find(|(layer_tag, ..)| layer_tag == tag);
+
+        match (tag, &mut level, layer_section) {
+            (tag, None, _) if tag == TILES_SECTION => {
+                if version > TILES_SECTION_VERSION {
+                    return Err(LevelFileError::UnsupportedSectionVersion {
+                        section: "tile",
+                        version,
+                    });
+                }
+                let mut section = ByteReader::new(section_bytes, "tile");
+                level = Some(read_tiles(&mut section)?);
+                section.finish()?;
+            }
+            (tag, Some(level), _) if tag == SPRITES_SECTION => {
+                if version > SPRITES_SECTION_VERSION {
+                    return Err(LevelFileError::UnsupportedSectionVersion {
+                        section: "sprite",
+                        version,
+                    });
+                }
+                let mut section = ByteReader::new(section_bytes, "sprite");
+                read_sprites(&mut section, level)?;
+                section.finish()?;
+            }
+            (tag, Some(level), _) if tag == FOG_SECTION => {
+                if version > FOG_SECTION_VERSION {
+                    return Err(LevelFileError::UnsupportedSectionVersion {
+                        section: "fog",
+                        version,
+                    });
+                }
+                let mut section = ByteReader::new(section_bytes, "fog");
+                read_fog(&mut section, level)?;
+                section.finish()?;
+            }
+            (tag, Some(level), _) if tag == MANIFEST_SECTION => {
+                if version > MANIFEST_SECTION_VERSION {
+                    return Err(LevelFileError::UnsupportedSectionVersion {
+                        section: "manifest",
+                        version,
+                    });
+                }
+                let path = std::str::from_utf8(section_bytes)
+                    .map_err(|_| LevelFileError::InvalidManifestPath)?;
+                level.manifest = Some(path.to_owned());
+            }
+            (tag, Some(level), _) if tag == SKY_SECTION => {
+                if version > SKY_SECTION_VERSION {
+                    return Err(LevelFileError::UnsupportedSectionVersion {
+                        section: "sky",
+                        version,
+                    });
+                }
+                let mut section = ByteReader::new(section_bytes, "sky");
+                level.sky = section.read_u8()?;
+                section.finish()?;
+            }
+            (_, Some(level), Some((_, layer, name, supported_version))) => {
+                if version > supported_version {
+                    return Err(LevelFileError::UnsupportedSectionVersion {
+                        section: name,
+                        version,
+                    });
+                }
+                let mut section = ByteReader::new(section_bytes, name);
+                read_layer(&mut section, level, layer)?;
+                section.finish()?;
+            }
+            (tag, None, _) if section_name(tag) != "unknown" => {
+                return Err(LevelFileError::SectionOutOfOrder(section_name(tag)));
+            }
+            // Skip over sections from newer versions of the editor
+            _ => {}
+        }
+    }
+
+    level.ok_or(LevelFileError::MissingSection("tile"))
+}
+
+// Reads a level out of the contents of a level file
+pub fn parse_level(bytes: &[u8]) -> Result<Level, LevelFileError> {
+    if !bytes.starts_with(&MAGIC) {
+        return read_v0(bytes);
+    }
+
+    let mut reader = ByteReader::new(bytes, "header");
+    reader.read_bytes(MAGIC.len())?;
+    match reader.read_u16()? {
+        FORMAT_VERSION => read_v1(&mut reader),
+        version => Err(LevelFileError::UnsupportedVersion(version)),
+    }
+}
+
+pub fn read_level_file(path: &str) -> Result<Level, LevelFileError> {
+    let mut level_file = File::open(path)?;
+    let mut bytes = vec![];
+    level_file.read_to_end(&mut bytes)?;
+
+    match LevelFormat::from_path(path) {
+        LevelFormat::Binary => parse_level(&bytes),
+        LevelFormat::Text => {
+            let text = String::from_utf8(bytes).map_err(|_| LevelFileError::Parse {
+                line: 0,
+                message: "Level file is not valid UTF-8".to_owned(),
+            })?;
+            parse_level_text(&text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::OPEN_SKY;
+
+    // A 3x2 level with one sprite, laid out the way write_level_file wrote
+    // levels before the files had a header (version 0)
+    #[rustfmt::skip]
+    const V0_LEVEL: [u8; 51] = [
+        // Width and height
+        0, 0, 0, 3, 0, 0, 0, 2,
+        // Spawn x (1.5) and y (0.5)
+        0x3f, 0xf8, 0, 0, 0, 0, 0, 0,
+        0x3f, 0xe0, 0, 0, 0, 0, 0, 0,
+        // Tiles
+        0, 0, 2,
+        1, 0, 2,
+        // One sprite of type 4 at (1.5, 1.5)
+        0, 0, 0, 1,
+        0x3f, 0xf8, 0, 0, 0, 0, 0, 0,
+        0x3f, 0xf8, 0, 0, 0, 0, 0, 0,
+        4,
+    ];
+
+    const ALL_LAYERS: [TileLayer; 5] = [
+        TileLayer::Walls,
+        TileLayer::Floor,
+        TileLayer::Ceiling,
+        TileLayer::Light,
+        TileLayer::Height,
+    ];
+
+    // Level with something other than the default in every field that gets saved
+    fn sample_level() -> Level {
+        let mut level = Level::new(4, 3);
+        (level.spawnx, level.spawny) = (1.5, 2.25);
+        level.set_tile(0, 0, 2);
+        level.set_tile(3, 2, 5);
+        level.set_layer_tile(TileLayer::Floor, 1, 1, 7);
+        level.set_layer_tile(TileLayer::Ceiling, 2, 0, 1);
+        level.set_layer_tile(TileLayer::Ceiling, 3, 0, OPEN_SKY);
+        level.set_layer_tile(TileLayer::Light, 3, 1, LIGHT_LEVELS - 1);
+        level.set_layer_tile(TileLayer::Height, 0, 0, HEIGHT_LEVELS - 1);
+        level.floor_color = [1, 2, 3];
+        level.ceiling_color = [4, 5, 6];
+        level.fog_color = [7, 8, 9];
+        level.fog_distance = 12.5;
+        level.sky = 13;
+        level.manifest = Some("my assets/manifest.txt".to_owned());
+        level.place_sprite(Sprite::new(1.5, 1.5, 4));
+        level.place_sprite(Sprite::new(2.5, 0.5, 6));
+        level
+    }
+
+    fn assert_same_level(loaded: &Level, expected: &Level) {
+        assert_eq!(
+            (loaded.width, loaded.height),
+            (expected.width, expected.height)
+        );
+        assert_eq!(
+            (loaded.spawnx, loaded.spawny),
+            (expected.spawnx, expected.spawny)
+        );
+        for layer in ALL_LAYERS {
+            assert_eq!(
+                loaded.layer_bytes(layer),
+                expected.layer_bytes(layer),
+                "{layer:?} layer"
+            );
+        }
+        assert_eq!(loaded.floor_color, expected.floor_color);
+        assert_eq!(loaded.ceiling_color, expected.ceiling_color);
+        assert_eq!(loaded.fog_color, expected.fog_color);
+        assert_eq!(loaded.fog_distance, expected.fog_distance);
+        assert_eq!(loaded.sky, expected.sky);
+        assert_eq!(loaded.manifest, expected.manifest);
+
+        let sprites = |level: &Level| -> Vec<(f64, f64, u8)> {
+            level
+                .sprites
+                .iter()
+                .map(|sprite| (sprite.pos.x, sprite.pos.y, sprite.sprite_type))
+                .collect()
+        };
+        assert_eq!(sprites(loaded), sprites(expected));
+    }
+
+    #[test]
+    fn loads_headerless_v0_level() {
+        let level = parse_level(&V0_LEVEL).unwrap();
+
+        assert_eq!((level.width, level.height), (3, 2));
+        assert_eq!((level.spawnx, level.spawny), (1.5, 0.5));
+        assert_eq!(level.layer_bytes(TileLayer::Walls), [0, 0, 2, 1, 0, 2]);
+        assert_eq!(level.sprites.len(), 1);
+        assert_eq!((level.sprites[0].pos.x, level.sprites[0].pos.y), (1.5, 1.5));
+        assert_eq!(level.sprites[0].sprite_type, 4);
+        // Everything that v0 files didn't have is left at the defaults
+        let defaults = Level::new(3, 2);
+        assert_eq!(level.floor_color, defaults.floor_color);
+        assert_eq!(level.layer_bytes(TileLayer::Light), [0; 6]);
+        assert_eq!(level.manifest, None);
+    }
+
+    #[test]
+    fn binary_round_trip_keeps_everything() {
+        let level = sample_level();
+        let loaded = parse_level(&level_to_bytes(&level)).unwrap();
+        assert_same_level(&loaded, &level);
+    }
+
+    #[test]
+    fn text_round_trip_keeps_everything() {
+        let level = sample_level();
+        let loaded = parse_level_text(&level_to_text(&level)).unwrap();
+        assert_same_level(&loaded, &level);
+    }
+
+    #[test]
+    fn text_and_binary_load_the_same_level() {
+        // Loading a text level and saving it as binary gives the same
+        // level as saving the original straight to binary
+        let level = sample_level();
+        let from_text = parse_level_text(&level_to_text(&level)).unwrap();
+        let bytes = level_to_bytes(&from_text);
+        assert_eq!(bytes, level_to_bytes(&level));
+        assert_same_level(&parse_level(&bytes).unwrap(), &level);
+    }
+
+    // Header followed by sections that are all version 1
+    fn v1_file(sections: &[([u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+        for (tag, data) in sections {
+            write_section(&mut bytes, tag, 1, data);
+        }
+        bytes
+    }
+
+    #[test]
+    fn rejects_repeated_sections() {
+        let level = sample_level();
+        let tiles = (TILES_SECTION, tiles_section(&level));
+
+        for repeated in [
+            tiles.clone(),
+            (SPRITES_SECTION, sprites_section(&level)),
+            (FLOOR_SECTION, layer_section(&level, TileLayer::Floor)),
+            (HEIGHT_SECTION, layer_section(&level, TileLayer::Height)),
+            (FOG_SECTION, fog_section(&level)),
+            (MANIFEST_SECTION, b"manifest.txt".to_vec()),
+            (SKY_SECTION, vec![1]),
+        ] {
+            let bytes = v1_file(&[tiles.clone(), repeated.clone(), repeated.clone()]);
+            let name = section_name(&repeated.0);
+            let err = parse_error(&bytes);
+            assert!(
+                matches!(err, LevelFileError::DuplicateSection(found) if found == name),
+                "{err:?}"
+            );
+        }
+
+        // Sections from newer versions can be in a file any number of times
+        let unknown = (*b"NEW!", vec![1, 2, 3]);
+        assert!(parse_level(&v1_file(&[tiles, unknown.clone(), unknown])).is_ok());
+    }
+
+    #[test]
+    fn rejects_repeated_text_sections() {
+        let text = level_to_text(&sample_level());
+        let lines: Vec<&str> = text.lines().collect();
+
+        for (keyword, name) in [
+            ("version", "version"),
+            ("size", "size"),
+            ("spawn", "spawn"),
+            ("tiles", "tile"),
+            ("sprites", "sprite"),
+            ("floor", "floor"),
+            ("ceiling", "ceiling"),
+            ("light", "light"),
+            ("height", "height"),
+            ("fog", "fog"),
+            ("manifest", "manifest"),
+            ("sky", "sky"),
+        ] {
+            // Repeat the section (up to its end line if it has one) right after itself
+            let start = lines
+                .iter()
+                .position(|line| line.split_whitespace().next() == Some(keyword))
+                .unwrap();
+            let has_rows = ["tiles", "sprites", "floor", "ceiling", "light", "height"];
+            let end = if has_rows.contains(&keyword) {
+                start
+                    + lines[start..]
+                        .iter()
+                        .position(|line| *line == "end")
+                        .unwrap()
+            } else {
+                start
+            };
+            let mut repeated = lines.clone();
+            repeated.splice(end + 1..end + 1, lines[start..=end].iter().copied());
+
+            let err = match parse_level_text(&repeated.join("\n")) {
+                Ok(_) => panic!("a level with two {keyword} sections was loaded"),
+                Err(err) => err,
+            };
+            assert!(
+                matches!(err, LevelFileError::DuplicateSection(found) if found == name),
+                "{keyword}: {err:?}"
+            );
+        }
+    }
+
+    fn parse_error(bytes: &[u8]) -> LevelFileError {
+        match parse_level(bytes) {
+            Ok(_) => panic!("a malformed level file was loaded"),
+            Err(err) => err,
+        }
+    }
+
+    // The v0 fixture with a different width and height
+    fn v0_with_size(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = V0_LEVEL.to_vec();
+        bytes[0..4].copy_from_slice(&width.to_be_bytes());
+        bytes[4..8].copy_from_slice(&height.to_be_bytes());
+        bytes
+    }
+
+    #[test]
+    fn rejects_truncated_tiles() {
+        // Cut off in the middle of the tiles
+        assert!(matches!(
+            parse_error(&V0_LEVEL[..26]),
+            LevelFileError::TruncatedSection("tile")
+        ));
+
+        // A tile section that is shorter than the level
+        let mut tiles = tiles_section(&sample_level());
+        tiles.pop();
+        assert!(matches!(
+            parse_error(&v1_file(&[(TILES_SECTION, tiles)])),
+            LevelFileError::TruncatedSection("tile")
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_sprite_count() {
+        // The sprite count is the 4 bytes after the 30 bytes of tiles
+        assert!(matches!(
+            parse_error(&V0_LEVEL[..32]),
+            LevelFileError::TruncatedSection("sprite")
+        ));
+    }
+
+    #[test]
+    fn rejects_levels_that_are_too_big() {
+        for (width, height) in [(1025, 2), (3, 1025), (u32::MAX, u32::MAX)] {
+            let err = parse_error(&v0_with_size(width, height));
+            let found = match err {
+                LevelFileError::DimensionsTooLarge { width, height } => (width, height),
+                err => panic!("{err:?}"),
+            };
+            assert_eq!(found, (width, height));
+        }
+    }
+
+    #[test]
+    fn rejects_sprite_type_0() {
+        let mut bytes = V0_LEVEL.to_vec();
+        *bytes.last_mut().unwrap() = 0;
+        assert!(matches!(
+            parse_error(&bytes),
+            LevelFileError::InvalidSpriteType(0)
+        ));
+    }
+
+    #[test]
+    fn rejects_section_longer_than_the_file() {
+        let mut bytes = v1_file(&[(TILES_SECTION, tiles_section(&sample_level()))]);
+        // The length comes after the header (6 bytes), tag and version
+        bytes[12..16].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(matches!(
+            parse_error(&bytes),
+            LevelFileError::TruncatedSection("tile")
+        ));
+    }
+
+    #[test]
+    fn rejects_trailing_bytes_after_v0_level() {
+        let mut bytes = V0_LEVEL.to_vec();
+        bytes.push(0);
+        assert!(matches!(
+            parse_error(&bytes),
+            LevelFileError::TrailingData {
+                section: "sprite",
+                bytes: 1
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_sprites_before_tiles() {
+        let level = sample_level();
+        let bytes = v1_file(&[
+            (SPRITES_SECTION, sprites_section(&level)),
+            (TILES_SECTION, tiles_section(&level)),
+        ]);
+        assert!(matches!(
+            parse_error(&bytes),
+            LevelFileError::SectionOutOfOrder("sprite")
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_format_version() {
+        let mut bytes = level_to_bytes(&sample_level());
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_be_bytes());
+        assert!(matches!(
+            parse_error(&bytes),
+            LevelFileError::UnsupportedVersion(version) if version == FORMAT_VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn corrupted_files_never_panic() {
+        let valid = level_to_bytes(&sample_level());
+        // Small LCG so that the same files get tried every run
+        let mut seed = 12345u64;
+        let mut random = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+
+        for _ in 0..20000 {
+            let mut bytes = valid.clone();
+            for _ in 0..3 {
+                let i = random() % bytes.len();
+                bytes[i] = random() as u8;
+            }
+            let _ = parse_level(&bytes);
+            let _ = parse_level(&bytes[..random() % bytes.len()]);
+        }
+    }
+}
@@ -0,0 +1,390 @@
+use crate::level_editor::level_file::{
+    check_dimensions, check_fog_distance, check_layer_tile, check_position, check_sprite_type,
+    place_loaded_sprite, LevelFileError,
+};
+use crate::{level::TileLayer, sprite::Sprite, Level};
+use std::str::{FromStr, SplitWhitespace};
+
+// Text version of the level file so that levels can be read and diffed,
+// it looks like this:
+//
+// # retro-fps level
+// version 1
+// size 3 2
+// spawn 1.5 0.5
+// tiles
+// 0 0 2
+// 1 0 2
+// end
+// sprites
+// 1.5 1.5 4
+// end
+// floor 64 64 64
+// 0 0 1
+// 1 1 1
+// end
+// ceiling 128 128 128
+// 0 0 255
+// 0 0 0
+// end
+// light
+// 0 0 3
+// 0 0 3
+// end
+// height
+// 0 0 2
+// 8 0 0
+// end
+// fog 0 0 0 12
+// manifest my_assets/manifest.txt
+// sky 13
+//
+// The numbers after floor and ceiling are the color used for untextured
+// (0) tiles, the light section has the light level of each tile (0 is
+// fully lit), the height section has the height of each tile's wall in
+// quarters of a tile (0 is the normal height of 1) and the fog line is the
+// fog color followed by how far away the fog hides everything (0 for no
+// fog). The manifest line is the path of the manifest that the level uses
+// (relative to the level file) and the sky line is the id of the texture
+// drawn on ceiling tiles that are open sky (255). The floor and ceiling
+// were added in version 2, the light and fog in version 3, the manifest in
+// version 4, the height in version 5 and the sky in version 6, all of them
+// are optional. Each section can only be given once.
+//
+// Blank lines and lines starting with '#' are ignored
+const TEXT_FORMAT_VERSION: u16 = 6;
+
+fn push_layer(text: &mut String, level: &Level, layer: TileLayer) {
+    for y in 0..level.height {
+        let row: Vec<String> = (0..level.width)
+            .map(|x| {
+                level
+                    .get_layer_tile(layer, x as isize, y as isize)
+                    .to_string()
+            })
+            .collect();
+        text.push_str(&row.join(" "));
+        text.push('\n');
+    }
+    text.push_str("end\n");
+}
+
+pub fn level_to_text(level: &Level) -> String {
+    let mut text = String::new();
+    text.push_str("# retro-fps level\n");
+    text.push_str(&format!("version {TEXT_FORMAT_VERSION}\n"));
+    text.push_str(&format!("size {} {}\n", level.width, level.height));
+    text.push_str(&format!("spawn {} {}\n", level.spawnx, level.spawny));
+
+    text.push_str("tiles\n");
+    push_layer(&mut text, level, TileLayer::Walls);
+
+    text.push_str("sprites\n");
+    for sprite in &level.sprites {
+        text.push_str(&format!(
+            "{} {} {}\n",
+            sprite.pos.x, sprite.pos.y, sprite.sprite_type
+        ));
+    }
+    text.push_str("end\n");
+
+    let [r, g, b] = level.floor_color;
+    text.push_str(&format!("floor {r} {g} {b}\n"));
+    push_layer(&mut text, level, TileLayer::Floor);
+    let [r, g, b] = level.ceiling_color;
+    text.push_str(&format!("ceiling {r} {g} {b}\n"));
+    push_layer(&mut text, level, TileLayer::Ceiling);
+    text.push_str("light\n");
+    push_layer(&mut text, level, TileLayer::Light);
+    text.push_str("height\n");
+    push_layer(&mut text, level, TileLayer::Height);
+    let [r, g, b] = level.fog_color;
+    text.push_str(&format!("fog {r} {g} {b} {}\n", level.fog_distance));
+    if let Some(manifest) = &level.manifest {
+        text.push_str(&format!("manifest {manifest}\n"));
+    }
+    if level.sky != 0 {
+        text.push_str(&format!("sky {}\n", level.sky));
+    }
+
+    text
+}
+
+fn parse_error(line: usize, message: &str) -> LevelFileError {
+    LevelFileError::Parse {
+        line,
+        message: message.to_owned(),
+    }
+}
+
+fn parse_value<T: FromStr>(line: usize, word: &str) -> Result<T, LevelFileError> {
+    word.parse::<T>()
+        .map_err(|_| parse_error(line, &format!("Invalid value: {word}")))
+}
+
+// Parses `count` whitespace separated values from the rest of a line
+fn parse_values<T: FromStr>(
+    line: usize,
+    words: SplitWhitespace,
+    count: usize,
+) -> Result<Vec<T>, LevelFileError> {
+    let values = words
+        .map(|word| parse_value(line, word))
+        .collect::<Result<Vec<T>, LevelFileError>>()?;
+
+    if values.len() != count {
+        return Err(parse_error(
+            line,
+            &format!("Expected {count} values, found {}", values.len()),
+        ));
+    }
+
+    Ok(values)
+}
+
+// Reads `height` rows of `width` tiles followed by an end line
+fn parse_layer<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+    width: u32,
+    height: u32,
+    layer: TileLayer,
+    section: &'static str,
+) -> Result<Vec<u8>, LevelFileError> {
+    let mut tiles = Vec::with_capacity(width as usize * height as usize);
+
+    for _ in 0..height {
+        let (row_num, row) = lines
+            .next()
+            .ok_or(LevelFileError::TruncatedSection(section))?;
+        let row = parse_values::<u8>(row_num, row.split_whitespace(), width as usize)?;
+
+        for tile in row {
+            check_layer_tile(layer, tile)?;
+            tiles.push(tile);
+        }
+    }
+
+    match lines.next() {
+        Some((_, "end")) => Ok(tiles),
+        Some((end_num, _)) => Err(parse_error(end_num, "Expected end")),
+        None => Err(LevelFileError::TruncatedSection(section)),
+    }
+}
+
+// Name of the section that a keyword starts, the same names are used for the binary format
+fn keyword_section(keyword: &str) -> Option<&'static str> {
+    match keyword {
+        "version" => Some("version"),
+        "size" => Some("size"),
+        "spawn" => Some("spawn"),
+        "tiles" => Some("tile"),
+        "sprites" => Some("sprite"),
+        "floor" => Some("floor"),
+        "ceiling" => Some("ceiling"),
+        "light" => Some("light"),
+        "height" => Some("height"),
+        "fog" => Some("fog"),
+        "manifest" => Some("manifest"),
+        "sky" => Some("sky"),
+        _ => None,
+    }
+}
+
+pub fn parse_level_text(text: &str) -> Result<Level, LevelFileError> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    let mut size = None;
+    let mut spawn = None;
+    let mut tiles = None;
+    let mut sprites = vec![];
+    // Untextured color and tiles of the floor and ceiling
+    let mut floor = None;
+    let mut ceiling = None;
+    let mut light = None;
+    let mut heights = None;
+    let mut fog = None;
+    let mut manifest = None;
+    let mut sky = 0;
+    // Every section can only be given once
+    let mut seen_sections: Vec<&'static str> = vec![];
+
+    while let Some((line_num, line)) = lines.next() {
+        let mut words = line.split_whitespace();
+        let keyword = words.next();
+
+        if let Some(section) = keyword.and_then(keyword_section) {
+            if seen_sections.contains(&section) {
+                return Err(LevelFileError::DuplicateSection(section));
+            }
+            seen_sections.push(section);
+        }
+
+        match keyword {
+            Some("version") => {
+                let version = parse_values::<u16>(line_num, words, 1)?[0];
+                if version > TEXT_FORMAT_VERSION {
+                    return Err(LevelFileError::UnsupportedVersion(version));
+                }
+            }
+            Some("size") => {
+                let values = parse_values::<u32>(line_num, words, 2)?;
+                check_dimensions(values[0], values[1])?;
+                size = Some((values[0], values[1]));
+            }
+            Some("spawn") => {
+                let values = parse_values::<f64>(line_num, words, 2)?;
+                check_position(values[0], values[1], "spawn")?;
+                spawn = Some((values[0], values[1]));
+            }
+            Some("tiles") => {
+                let (width, height) =
+                    size.ok_or_else(|| parse_error(line_num, "tiles must come after size"))?;
+                tiles = Some(parse_layer(
+                    &mut lines,
+                    width,
+                    height,
+                    TileLayer::Walls,
+                    "tile",
+                )?);
+            }
+            Some(keyword @ ("floor" | "ceiling")) => {
+                let (width, height) = size.ok_or_else(|| {
+                    parse_error(line_num, &format!("{keyword} must come after size"))
+                })?;
+                let color = parse_values::<u8>(line_num, words, 3)?;
+                let color = [color[0], color[1], color[2]];
+
+                if keyword == "floor" {
+                    let layer = parse_layer(&mut lines, width, height, TileLayer::Floor, "floor")?;
+                    floor = Some((color, layer));
+                } else {
+                    let layer =
+                        parse_layer(&mut lines, width, height, TileLayer::Ceiling, "ceiling")?;
+                    ceiling = Some((color, layer));
+                }
+            }
+            Some("light") => {
+                let (width, height) =
+                    size.ok_or_else(|| parse_error(line_num, "light must come after size"))?;
+                light = Some(parse_layer(
+                    &mut lines,
+                    width,
+                    height,
+                    TileLayer::Light,
+                    "light",
+                )?);
+            }
+            Some("height") => {
+                let (width, height) =
+                    size.ok_or_else(|| parse_error(line_num, "height must come after size"))?;
+                heights = Some(parse_layer(
+                    &mut lines,
+                    width,
+                    height,
+                    TileLayer::Height,
+                    "height",
+                )?);
+            }
+            Some("fog") => {
+                let words: Vec<&str> = words.collect();
+                if words.len() != 4 {
+                    return Err(parse_error(
+                        line_num,
+                        &format!("Expected 4 values, found {}", words.len()),
+                    ));
+                }
+
+                let mut color = [0u8; 3];
+                for (channel, word) in color.iter_mut().zip(&words) {
+                    *channel = parse_value::<u8>(line_num, word)?;
+                }
+                let distance = parse_value::<f64>(line_num, words[3])?;
+                check_fog_distance(distance)?;
+                fog = Some((color, distance));
+            }
+            Some("manifest") => {
+                // The rest of the line is the path so that it can have spaces in it
+                let path = line["manifest".len()..].trim();
+                if path.is_empty() {
+                    return Err(parse_error(line_num, "Expected a path after manifest"));
+                }
+                manifest = Some(path.to_owned());
+            }
+            Some("sky") => {
+                sky = parse_values::<u8>(line_num, words, 1)?[0];
+            }
+            Some("sprites") => loop {
+                match lines.next() {
+                    Some((_, "end")) => break,
+                    Some((sprite_num, sprite_line)) => {
+                        let words: Vec<&str> = sprite_line.split_whitespace().collect();
+                        if words.len() != 3 {
+                            return Err(parse_error(
+                                sprite_num,
+                                &format!("Expected 3 values, found {}", words.len()),
+                            ));
+                        }
+
+                        let x = parse_value::<f64>(sprite_num, words[0])?;
+                        let y = parse_value::<f64>(sprite_num, words[1])?;
+                        let sprite_type = parse_value::<u8>(sprite_num, words[2])?;
+                        check_position(x, y, "sprite")?;
+                        check_sprite_type(sprite_type)?;
+
+                        sprites.push(Sprite::new(x, y, sprite_type));
+                    }
+                    None => return Err(LevelFileError::TruncatedSection("sprite")),
+                }
+            },
+            Some(word) => {
+                return Err(parse_error(line_num, &format!("Unknown keyword: {word}")));
+            }
+            None => {}
+        }
+    }
+
+    let tiles = tiles.ok_or(LevelFileError::MissingSection("tile"))?;
+    let (width, height) = size.ok_or(LevelFileError::MissingSection("size"))?;
+    let mut level = Level::new(width, height);
+    (level.spawnx, level.spawny) = spawn.ok_or(LevelFileError::MissingSection("spawn"))?;
+    level.manifest = manifest;
+    level.sky = sky;
+
+    let mut layers = vec![(TileLayer::Walls, tiles)];
+    if let Some((color, floor_tiles)) = floor {
+        level.floor_color = color;
+        layers.push((TileLayer::Floor, floor_tiles));
+    }
+    if let Some((color, ceiling_tiles)) = ceiling {
+        level.ceiling_color = color;
+        layers.push((TileLayer::Ceiling, ceiling_tiles));
+    }
+
+    if let Some(light_levels) = light {
+        layers.push((TileLayer::Light, light_levels));
+    }
+    if let Some(wall_heights) = heights {
+        layers.push((TileLayer::Height, wall_heights));
+    }
+    if let Some((color, distance)) = fog {
+        level.fog_color = color;
+        level.fog_distance = distance;
+    }
+
+    for (layer, layer_tiles) in layers {
+        for (i, tile) in layer_tiles.into_iter().enumerate() {
+            let (x, y) = (i % width as usize, i / width as usize);
+            level.set_layer_tile(layer, x as isize, y as isize, tile);
+        }
+    }
+
+    for sprite in sprites {
+        place_loaded_sprite(&mut level, sprite);
+    }
+
+    Ok(level)
+}
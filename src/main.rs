@@ -2,30 +2,52 @@ use sdl2::image::LoadTexture;
 use sdl2::keyboard::Scancode;
 use sdl2::mouse::MouseButton;
 use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::{BlendMode, Canvas, Texture};
-use sdl2::video::Window;
-use std::cmp::Ordering;
+use sdl2::render::{BlendMode, Canvas};
+use sdl2::video::{FullscreenType, Window};
 use std::time::Instant;
 
+mod animation;
 mod camera;
+mod collision;
+mod config;
+mod door;
+mod enemy;
 mod events;
+mod framebuffer;
 mod game;
 mod level;
 mod level_editor;
+mod manifest;
 mod menu;
+mod pathfinding;
+mod player;
 mod raycast;
+mod segment;
 mod sprite;
+mod viewport;
+mod weapon;
 
 use camera::Camera;
+use config::{Config, CONFIG_PATH};
 use events::{can_quit, InputState};
-use game::{display_level, game_update};
-use level::Level;
+use framebuffer::FrameBuffer;
+use game::{
+    display_hud, display_level, game_update, render_level, sort_sprites, GameState, GameView,
+    COLUMN_WIDTH,
+};
+use level::{Level, TileLayer, OPEN_SKY};
 use level_editor::{
-    display_level_editor, handle_mouse_input_editor, level_editor_menu::load_default_assets,
-    level_editor_menu::load_default_sprites, level_editor_menu::EditorMode,
-    level_editor_menu::LevelEditorMenu,
+    display_level_editor,
+    file_dialog::{FileDialog, FileDialogAction, FileDialogMode},
+    handle_mouse_input_editor,
+    level_editor_menu::{
+        create_height_swatches, create_light_swatches, numbered_icons, EditorMode, EditorTextures,
+        LevelEditorMenu,
+    },
+    EditorGrid, EditorState,
 };
+use manifest::{level_manifest_path, move_level_manifest, Assets, DEFAULT_MANIFEST_PATH};
+use viewport::Viewport;
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 enum GameMode {
@@ -33,11 +55,14 @@ enum GameMode {
     Game,
 }
 
-fn switch_modes(game_mode: &GameMode, camera: &mut Camera, level: &Level) -> GameMode {
+fn switch_modes(
+    game_mode: &GameMode,
+    level: &Level,
+    game_state: &mut GameState,
+    assets: &Assets,
+) -> GameMode {
     if *game_mode == GameMode::Editor {
-        camera.position.x = level.spawnx;
-        camera.position.y = level.spawny;
-        camera.rotation = 0.0;
+        *game_state = GameState::new(level, game_state.config.clone(), assets);
         return GameMode::Game;
     } else if *game_mode == GameMode::Game {
         return GameMode::Editor;
@@ -46,59 +71,122 @@ fn switch_modes(game_mode: &GameMode, camera: &mut Camera, level: &Level) -> Gam
     *game_mode
 }
 
-fn display(
+// `old_path` is the file the level came from, the level's own manifest is relative to it
+fn save_level(level: &mut Level, old_path: Option<&str>, path: &str) -> Result<(), String> {
+    let old_manifest = level.manifest.clone();
+    move_level_manifest(level, old_path.unwrap_or(""), path);
+    level_editor::level_file::write_level_file(level, path).map_err(|msg| {
+        level.manifest = old_manifest;
+        format!("Failed to save level: {msg}")
+    })
+}
+
+// Reads a level and the assets from the manifest that it uses
+fn read_level_and_assets(path: &str) -> Result<(Level, Assets), String> {
+    let level = level_editor::level_file::read_level_file(path).map_err(|e| e.to_string())?;
+    let assets = Assets::load(&level_manifest_path(&level, path))?;
+    assets
+        .manifest
+        .check_level(&level)
+        .map_err(|e| e.to_string())?;
+    Ok((level, assets))
+}
+
+fn load_level(path: &str) -> Result<(Level, Assets), String> {
+    read_level_and_assets(path).map_err(|msg| format!("Failed to load level: {msg}"))
+}
+
+fn set_window_title(
     canvas: &mut Canvas<Window>,
-    game_mode: &GameMode,
-    camera: &Camera,
-    level: &Level,
-    textures: &mut [Texture],
-    sprite_images: &[Texture],
-    input_state: &InputState,
+    current_file: &Option<String>,
 ) -> Result<(), String> {
-    canvas.set_draw_color(Color::BLACK);
-    canvas.clear();
+    let title = match current_file {
+        Some(path) => format!("Retro FPS - {path}"),
+        None => "Retro FPS".to_owned(),
+    };
 
-    match game_mode {
-        GameMode::Editor => {
-            display_level_editor(canvas, level, input_state, &*textures, sprite_images)?;
-        }
-        GameMode::Game => {
-            display_level(canvas, camera, level, textures, sprite_images, 2)?;
-        }
-    }
+    canvas
+        .window_mut()
+        .set_title(&title)
+        .map_err(|e| e.to_string())
+}
 
-    for texture in textures {
-        texture.set_color_mod(255, 255, 255);
-    }
+// Switches between a window and borderless fullscreen
+fn toggle_fullscreen(canvas: &mut Canvas<Window>) -> Result<(), String> {
+    let window = canvas.window_mut();
+    let fullscreen = match window.fullscreen_state() {
+        FullscreenType::Off => FullscreenType::Desktop,
+        _ => FullscreenType::Off,
+    };
 
-    Ok(())
+    window.set_fullscreen(fullscreen)
 }
 
 fn update(
     game_mode: &GameMode,
     level: &mut Level,
-    camera: &mut Camera,
+    game_state: &mut GameState,
     input_state: &InputState,
-    selected_tile: u8,
-    editor_mode: &EditorMode,
+    level_editor_menu: &LevelEditorMenu,
+    editor_state: &mut EditorState,
     dt: f64,
 ) {
     match game_mode {
         GameMode::Editor => {
-            handle_mouse_input_editor(level, input_state, selected_tile, editor_mode);
+            handle_mouse_input_editor(level, input_state, level_editor_menu, editor_state);
         }
         GameMode::Game => {
-            game_update(level, camera, input_state, dt);
+            game_update(game_state, input_state, dt);
         }
     }
 }
 
+// Renders a single frame of a level from its spawn point without
+// opening a window and saves it as a bitmap, if a golden image is
+// passed in then the frame is compared against it
+// usage: retro-fps --render <level file> <output.bmp> [golden.bmp]
+fn render_headless(args: &[String]) -> Result<(), String> {
+    if args.len() < 2 {
+        return Err("usage: --render <level file> <output.bmp> [golden.bmp]".to_owned());
+    }
+
+    let (level, assets) = read_level_and_assets(&args[0])?;
+    let mut game_state = GameState::new(&level, Config::default(), &assets);
+    sort_sprites(&mut game_state.level.sprites, &game_state.camera);
+
+    let mut framebuffer = FrameBuffer::new(800, 640);
+    render_level(
+        &mut framebuffer,
+        &game_state.camera,
+        &game_state.level,
+        &game_state.doors,
+        &assets,
+        COLUMN_WIDTH,
+    );
+    framebuffer.save_bmp(&args[1])?;
+
+    if let Some(golden) = args.get(2) {
+        let differences = framebuffer.count_differences(golden)?;
+        if differences > 0 {
+            return Err(format!("{differences} pixels differ from {golden}"));
+        }
+    }
+
+    Ok(())
+}
+
 fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 && args[1] == "--render" {
+        return render_headless(&args[2..]);
+    }
+
     let ctx = sdl2::init()?;
     let vid_subsystem = ctx.video()?;
     let window = vid_subsystem
         .window("Retro FPS", 960, 640)
         .position_centered()
+        .resizable()
         .build()
         .map_err(|e| e.to_string())?;
     let mut canvas = window
@@ -106,6 +194,11 @@ fn main() -> Result<(), String> {
         .present_vsync()
         .build()
         .map_err(|e| e.to_string())?;
+    // Small enough to fit anywhere, big enough for the editor menu and file dialog
+    canvas
+        .window_mut()
+        .set_minimum_size(640, 480)
+        .map_err(|e| e.to_string())?;
     canvas.set_blend_mode(BlendMode::Blend);
     let mut event_pump = ctx.event_pump()?;
 
@@ -113,119 +206,255 @@ fn main() -> Result<(), String> {
     let texture_creator = canvas.texture_creator();
 
     let mut level_editor_menu = LevelEditorMenu::new();
-    let mut textures = load_default_assets(&texture_creator);
-    let sprite_images = load_default_sprites(&texture_creator);
+    // Textures and sprites come from the manifest of the level that is being edited
+    let mut assets = Assets::load(DEFAULT_MANIFEST_PATH)?;
+    let mut editor_textures = EditorTextures::new(&texture_creator, &assets)?;
+    let mut game_view = GameView::new(&texture_creator, &assets)?;
+    let light_swatches = create_light_swatches(&texture_creator)?;
+    let height_swatches = create_height_swatches(&texture_creator)?;
     let player_spawn_icon = texture_creator.load_texture("assets/images/player_spawn_icon.png")?;
 
     let mut level = Level::new(40, 40);
-    let mut camera = Camera::new(0.5, 0.5, 0.0, std::f64::consts::PI / 12.0 * 5.0);
+    let mut game_state = GameState::new(&level, Config::load(CONFIG_PATH), &assets);
     let mut input_state = InputState::new();
     let mut game_mode = GameMode::Editor;
     let mut dt = 0.0f64;
+    // Path of the level that is being edited, Ctrl+S saves to this
+    let mut current_file: Option<String> = None;
+    let mut file_dialog: Option<FileDialog> = None;
+    let mut editor_state = EditorState::new();
 
     let font_8_bit_operator =
         ttf_ctx.load_font("assets/fonts/8BitOperator/8bitOperatorPlus-Regular.ttf", 64)?;
 
     //Main loop
-    while !can_quit(&mut event_pump) {
+    while !can_quit(&mut event_pump, &mut input_state) {
         let frame_start = Instant::now();
+        let previous_mode = game_mode;
+        // Input that closes the dialog shouldn't also edit the level
+        let dialog_open = file_dialog.is_some();
 
-        level.sprites.sort_by(|sprite1, sprite2| {
-            let sprite1_trans_x = sprite1.pos.x - camera.position.x;
-            let sprite1_trans_y = sprite1.pos.y - camera.position.y;
-            let sprite1_rotated_y = sprite1_trans_x * (-camera.rotation).cos()
-                - sprite1_trans_y * (-camera.rotation).sin();
+        sort_sprites(&mut game_state.level.sprites, &game_state.camera);
 
-            let sprite2_trans_x = sprite2.pos.x - camera.position.x;
-            let sprite2_trans_y = sprite2.pos.y - camera.position.y;
-            let sprite2_rotated_y = sprite2_trans_x * (-camera.rotation).cos()
-                - sprite2_trans_y * (-camera.rotation).sin();
+        // Everything is laid out again every frame so that it
+        // follows the window when it gets resized
+        let (window_w, window_h) = canvas.output_size()?;
+        let viewport = Viewport::fit(window_w, window_h, game_state.config.render_scale);
+        level_editor_menu.resize(window_w, window_h);
+        // The level gets whatever space the menu leaves
+        let grid_w = level_editor_menu.menu.x().max(0) as u32;
+        editor_state.grid = EditorGrid::fit(&level, grid_w, window_h);
+        if let Some(dialog) = &mut file_dialog {
+            dialog.set_center(window_w as i32 / 2, window_h as i32 / 2);
+        }
 
-            if sprite2_rotated_y < sprite1_rotated_y {
-                Ordering::Less
-            } else {
-                Ordering::Greater
+        canvas.set_draw_color(Color::BLACK);
+        canvas.clear();
+
+        match game_mode {
+            GameMode::Editor => {
+                display_level_editor(
+                    &mut canvas,
+                    &level,
+                    &input_state,
+                    &editor_textures,
+                    &editor_state.grid,
+                    level_editor_menu.layer,
+                )?;
+            }
+            GameMode::Game => {
+                display_level(&mut canvas, &mut game_view, &game_state, &viewport)?;
+                display_hud(
+                    &mut canvas,
+                    &game_state,
+                    &editor_textures.sprite_images,
+                    &texture_creator,
+                    &font_8_bit_operator,
+                    &viewport,
+                )?;
             }
-        });
-
-        display(
-            &mut canvas,
-            &game_mode,
-            &camera,
-            &level,
-            &mut textures,
-            &sprite_images,
-            &input_state,
-        )?;
+        }
 
         if game_mode == GameMode::Editor {
             match level_editor_menu.editor_mode {
                 EditorMode::Tiles => {
+                    // The light and height layers are painted with
+                    // light levels and wall heights instead of textures
+                    let icons = match level_editor_menu.layer {
+                        TileLayer::Light => numbered_icons(&light_swatches),
+                        TileLayer::Height => numbered_icons(&height_swatches),
+                        layer => editor_textures.tile_icons(layer),
+                    };
+
                     level_editor_menu.display(
                         &mut canvas,
                         &input_state,
                         &texture_creator,
                         &font_8_bit_operator,
-                        &textures,
+                        &icons,
+                        &[],
                     )?;
-                    level_editor_menu.handle_mouse_input(&input_state, textures.len() as u8);
+                    if file_dialog.is_none() {
+                        level_editor_menu.handle_mouse_input(&input_state, &icons);
+                    }
                 }
                 EditorMode::Sprites => {
+                    let icons = editor_textures.sprite_icons();
                     level_editor_menu.display(
                         &mut canvas,
                         &input_state,
                         &texture_creator,
                         &font_8_bit_operator,
-                        &sprite_images,
+                        &icons,
+                        &editor_textures.sprite_frames,
                     )?;
-                    level_editor_menu.handle_mouse_input(&input_state, sprite_images.len() as u8);
+                    if file_dialog.is_none() {
+                        level_editor_menu.handle_mouse_input(&input_state, &icons);
+                    }
                 }
             }
 
             canvas.copy(
                 &player_spawn_icon,
                 None,
-                Rect::new(level.spawnx as i32 * 16, level.spawny as i32 * 16, 16, 16),
+                editor_state
+                    .grid
+                    .tile_rect(level.spawnx as isize, level.spawny as isize),
             )?;
 
-            let clicked = level_editor_menu
-                .menu
-                .get_clicked(&input_state, MouseButton::Left)
-                .unwrap_or("".to_owned());
-
-            if clicked == "play_button" {
-                game_mode = switch_modes(&game_mode, &mut camera, &level);
-            } else if clicked == "save_button" {
-                level_editor::level_file::write_level_file(&level, "saved_level")?;
-            } else if clicked == "load_button" {
-                level = level_editor::level_file::read_level_file("saved_level")?;
-            } else if clicked == "sprite_button" {
-                level_editor_menu.selected = 1;
-                level_editor_menu.editor_mode = EditorMode::Sprites;
-            } else if clicked == "tile_button" {
-                level_editor_menu.selected = 1;
-                level_editor_menu.editor_mode = EditorMode::Tiles;
+            if let Some(dialog) = &mut file_dialog {
+                dialog.display(
+                    &mut canvas,
+                    &input_state,
+                    &texture_creator,
+                    &font_8_bit_operator,
+                )?;
+
+                match dialog.update(&input_state) {
+                    FileDialogAction::Save(path) => {
+                        match save_level(&mut level, current_file.as_deref(), &path) {
+                            Ok(()) => {
+                                current_file = Some(path);
+                                set_window_title(&mut canvas, &current_file)?;
+                                file_dialog = None;
+                            }
+                            Err(msg) => dialog.show_error(&msg),
+                        }
+                    }
+                    FileDialogAction::Open(path) => {
+                        match load_level(&path) {
+                            Ok((loaded_level, loaded_assets)) => {
+                                editor_textures =
+                                    EditorTextures::new(&texture_creator, &loaded_assets)?;
+                                game_view = GameView::new(&texture_creator, &loaded_assets)?;
+                                assets = loaded_assets;
+                                level = loaded_level;
+                                // The level's manifest might not have the selected texture
+                                level_editor_menu.selected = 1;
+                                editor_state.history.clear();
+                                current_file = Some(path);
+                                set_window_title(&mut canvas, &current_file)?;
+                                file_dialog = None;
+                            }
+                            Err(msg) => dialog.show_error(&msg),
+                        }
+                    }
+                    FileDialogAction::Cancel => file_dialog = None,
+                    FileDialogAction::None => {}
+                }
+            } else {
+                let clicked = level_editor_menu
+                    .menu
+                    .get_clicked(&input_state, MouseButton::Left)
+                    .unwrap_or("".to_owned());
+
+                let ctrl_held = input_state.key_is_held(Scancode::LCtrl)
+                    || input_state.key_is_held(Scancode::RCtrl);
+
+                if clicked == "play_button" {
+                    game_mode = switch_modes(&game_mode, &level, &mut game_state, &assets);
+                } else if clicked == "save_button" {
+                    file_dialog = Some(FileDialog::new(
+                        FileDialogMode::Save,
+                        current_file.as_deref(),
+                    ));
+                } else if clicked == "load_button" {
+                    file_dialog = Some(FileDialog::new(
+                        FileDialogMode::Open,
+                        current_file.as_deref(),
+                    ));
+                } else if clicked == "sprite_button" {
+                    level_editor_menu.selected = 1;
+                    level_editor_menu.editor_mode = EditorMode::Sprites;
+                } else if clicked == "tile_button" {
+                    level_editor_menu.selected = 1;
+                    level_editor_menu.editor_mode = EditorMode::Tiles;
+                } else if let Some(layer) = LevelEditorMenu::layer_for_button(&clicked) {
+                    // Light levels, wall heights and textures don't share ids,
+                    // and open sky can only be painted on the ceiling
+                    let shares_ids = layer == level_editor_menu.layer
+                        || (layer.is_textured()
+                            && level_editor_menu.layer.is_textured()
+                            && level_editor_menu.selected != OPEN_SKY);
+                    if matches!(level_editor_menu.editor_mode, EditorMode::Sprites) || !shares_ids {
+                        level_editor_menu.selected = 1;
+                    }
+                    level_editor_menu.set_layer(layer);
+                    level_editor_menu.editor_mode = EditorMode::Tiles;
+                } else if ctrl_held && input_state.key_is_clicked(Scancode::Z) {
+                    editor_state.undo(&mut level);
+                } else if ctrl_held && input_state.key_is_clicked(Scancode::Y) {
+                    editor_state.redo(&mut level);
+                } else if ctrl_held && input_state.key_is_clicked(Scancode::S) {
+                    // Save back to the current file, if there isn't one ask for a name
+                    match &current_file {
+                        Some(path) => {
+                            // Ask where to save instead if the file can't be written
+                            if let Err(msg) = save_level(&mut level, Some(path), path) {
+                                let mut dialog = FileDialog::new(FileDialogMode::Save, Some(path));
+                                dialog.show_error(&msg);
+                                file_dialog = Some(dialog);
+                            }
+                        }
+                        None => {
+                            file_dialog = Some(FileDialog::new(FileDialogMode::Save, None));
+                        }
+                    }
+                }
             }
         }
 
         canvas.present();
 
-        update(
-            &game_mode,
-            &mut level,
-            &mut camera,
-            &input_state,
-            level_editor_menu.selected,
-            &level_editor_menu.editor_mode,
-            dt,
-        );
-
-        game_mode = if input_state.key_is_clicked(Scancode::P) {
-            switch_modes(&game_mode, &mut camera, &level)
+        // Don't edit the level while the file dialog is open
+        if !dialog_open {
+            update(
+                &game_mode,
+                &mut level,
+                &mut game_state,
+                &input_state,
+                &level_editor_menu,
+                &mut editor_state,
+                dt,
+            );
+        }
+
+        game_mode = if input_state.key_is_clicked(Scancode::P) && !dialog_open {
+            switch_modes(&game_mode, &level, &mut game_state, &assets)
         } else {
             game_mode
         };
 
+        if input_state.key_is_clicked(Scancode::F11) {
+            toggle_fullscreen(&mut canvas)?;
+        }
+
+        // Hide the cursor and use mouse look while playing
+        if game_mode != previous_mode {
+            ctx.mouse()
+                .set_relative_mouse_mode(game_mode == GameMode::Game);
+        }
+
         input_state.update(&event_pump);
 
         //Calculate how much time has elapsed in the frame
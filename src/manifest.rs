@@ -0,0 +1,623 @@
+use crate::animation::SpriteDef;
+use crate::enemy::EnemyKind;
+use crate::framebuffer::TextureData;
+use crate::level::{TileLayer, OPEN_SKY};
+use crate::level_editor::level_file::LevelFileError;
+use crate::player::KeyColor;
+use crate::segment::Segment;
+use crate::sprite::{new_vector2, Vector2f64};
+use crate::Level;
+use std::path::Path;
+use std::rc::Rc;
+
+// Manifest that is used for levels that don't have their own
+pub const DEFAULT_MANIFEST_PATH: &str = "assets/manifest.txt";
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Pickup {
+    Key(KeyColor),
+    Health,
+    Ammo,
+}
+
+// Path of the manifest that a level uses, a level's own manifest
+// is relative to the level file
+pub fn level_manifest_path(level: &Level, level_path: &str) -> String {
+    match &level.manifest {
+        Some(manifest) => Path::new(level_path)
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(manifest)
+            .to_string_lossy()
+            .into_owned(),
+        None => DEFAULT_MANIFEST_PATH.to_owned(),
+    }
+}
+
+// Points a level's own manifest at the same file from wherever the level is saved next,
+// `old_path` is the file the level was loaded from
+pub fn move_level_manifest(level: &mut Level, old_path: &str, new_path: &str) {
+    if level.manifest.is_none() {
+        return;
+    }
+
+    let manifest = Path::new(&level_manifest_path(level, old_path)).canonicalize();
+    let directory = Path::new(new_path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .canonicalize();
+    // Leave the path alone if there's no manifest to point at
+    let (Ok(manifest), Ok(directory)) = (manifest, directory) else {
+        return;
+    };
+
+    let shared = manifest
+        .components()
+        .zip(directory.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    // Files on different drives can only be reached with the full path
+    if shared == 0 {
+        level.manifest = Some(manifest.to_string_lossy().into_owned());
+        return;
+    }
+
+    // Forward slashes work everywhere, so levels can be shared between systems
+    let parts: Vec<String> = directory
+        .components()
+        .skip(shared)
+        .map(|_| "..".to_owned())
+        .chain(
+            manifest
+                .components()
+                .skip(shared)
+                .map(|part| part.as_os_str().to_string_lossy().into_owned()),
+        )
+        .collect();
+    level.manifest = Some(parts.join("/"));
+}
+
+// What a tile does in the game, tiles are solid walls unless
+// the manifest says otherwise
+#[derive(Clone, Copy)]
+pub struct TileProperties {
+    // Blocks movement
+    pub solid: bool,
+    // Can be seen and shot through
+    pub transparent: bool,
+    // Doors open when they are used, `key` is the key needed to open them
+    pub door: bool,
+    pub key: Option<KeyColor>,
+    // Using the tile finishes the level
+    pub exit: bool,
+    // Damage per second dealt to the player while standing on the tile
+    pub damage: f64,
+    // Using the tile opens every tile with this id
+    pub switch_target: Option<u8>,
+    // Tiles with a segment are a thin wall along it instead of a block
+    pub segment: Option<Segment>,
+    // Panoramas that levels can use as their sky, these aren't tiles
+    // so they can't be painted in the editor
+    pub sky: bool,
+}
+
+impl Default for TileProperties {
+    fn default() -> Self {
+        Self {
+            solid: true,
+            transparent: false,
+            door: false,
+            key: None,
+            exit: false,
+            damage: 0.0,
+            switch_target: None,
+            segment: None,
+            sky: false,
+        }
+    }
+}
+
+// What a sprite does in the game
+#[derive(Clone, Copy, Default)]
+pub struct SpriteProperties {
+    // Blocks movement
+    pub solid: bool,
+    pub pickup: Option<Pickup>,
+    pub enemy: Option<EnemyKind>,
+    // Blows up after being shot
+    pub explosive: bool,
+}
+
+pub struct AssetEntry<P> {
+    pub name: String,
+    // Path of the image, relative to the working directory
+    pub path: String,
+    pub properties: P,
+}
+
+// List of every texture and sprite that levels can use, the manifest file
+// has one asset per line:
+//
+// texture <id> <name> <path> [properties]
+// sprite <id> <name> <path> [properties]
+//
+// Ids go from 1 to 255 and are what levels store, so they shouldn't change
+// once levels use them. Paths are relative to the manifest file.
+//
+// Textures are also the tiles of the level, they are solid walls unless
+// they have `passable` and can have `transparent`, `door [key color]`,
+// `exit`, `damage <per second>`, `switch <tile id>` and `segment x1 y1 x2 y2`
+// (a thin wall between two points in the tile, from 0 to 1). Textures with
+// `sky` are only used as a level's sky, not as tiles.
+// Sprites can have `solid`, `explosive`, `enemy <type>` and
+// `pickup <health, ammo or key <color>>`. The default manifest is
+// empty, so every tile in a level is a solid wall
+#[derive(Default)]
+pub struct Manifest {
+    // Indexed by id - 1, None for ids that aren't used
+    textures: Vec<Option<AssetEntry<TileProperties>>>,
+    sprites: Vec<Option<AssetEntry<SpriteProperties>>>,
+}
+
+fn parse_tile_properties(words: &[&str]) -> Result<TileProperties, String> {
+    let mut properties = TileProperties::default();
+    let mut words = words.iter().peekable();
+
+    while let Some(word) = words.next() {
+        match *word {
+            "passable" => properties.solid = false,
+            "transparent" => properties.transparent = true,
+            "exit" => properties.exit = true,
+            "sky" => properties.sky = true,
+            "door" => {
+                properties.door = true;
+                // Doors without a key color can be opened by anyone
+                properties.key = words.peek().and_then(|word| KeyColor::from_name(word));
+                if properties.key.is_some() {
+                    words.next();
+                }
+            }
+            "damage" => {
+                let damage = words.next().ok_or("expected the damage per second")?;
+                properties.damage = damage
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|damage| damage.is_finite() && *damage >= 0.0)
+                    .ok_or(format!("invalid damage `{damage}`"))?;
+            }
+            "switch" => {
+                let target = words.next().ok_or("expected the id of the tile to open")?;
+                properties.switch_target = match target.parse::<u8>() {
+                    Ok(target) if target > 0 => Some(target),
+                    _ => return Err(format!("invalid tile id `{target}`")),
+                };
+            }
+            "segment" => {
+                let mut point = || -> Result<Vector2f64, String> {
+                    let mut coordinate = || {
+                        let word = words.next().ok_or("expected 4 numbers after segment")?;
+                        word.parse::<f64>()
+                            .ok()
+                            .filter(|value| (0.0..=1.0).contains(value))
+                            .ok_or(format!(
+                                "invalid segment coordinate `{word}`, they go from 0 to 1"
+                            ))
+                    };
+                    Ok(new_vector2(coordinate()?, coordinate()?))
+                };
+                let (start, end) = (point()?, point()?);
+                if start.x == end.x && start.y == end.y {
+                    return Err("the ends of a segment can't be the same point".to_owned());
+                }
+                properties.segment = Some(Segment { start, end });
+            }
+            _ => return Err(format!("unknown texture property `{word}`")),
+        }
+    }
+
+    Ok(properties)
+}
+
+fn parse_sprite_properties(words: &[&str]) -> Result<SpriteProperties, String> {
+    let mut properties = SpriteProperties::default();
+    let mut words = words.iter();
+
+    let color = |word: Option<&&str>| -> Result<KeyColor, String> {
+        let word = word.ok_or("expected a key color")?;
+        KeyColor::from_name(word).ok_or(format!("unknown key color `{word}`"))
+    };
+
+    while let Some(word) = words.next() {
+        match *word {
+            "solid" => properties.solid = true,
+            "explosive" => properties.explosive = true,
+            "enemy" => {
+                let name = words.next().ok_or("expected an enemy type")?;
+                let enemy =
+                    EnemyKind::from_name(name).ok_or(format!("unknown enemy type `{name}`"))?;
+                properties.enemy = Some(enemy);
+            }
+            "pickup" => {
+                properties.pickup = Some(match words.next() {
+                    Some(&"health") => Pickup::Health,
+                    Some(&"ammo") => Pickup::Ammo,
+                    Some(&"key") => Pickup::Key(color(words.next())?),
+                    _ => return Err("expected health, ammo or key after pickup".to_owned()),
+                });
+            }
+            _ => return Err(format!("unknown sprite property `{word}`")),
+        }
+    }
+
+    Ok(properties)
+}
+
+fn get_entry<P>(entries: &[Option<AssetEntry<P>>], id: u8) -> Option<&AssetEntry<P>> {
+    entries.get((id as usize).checked_sub(1)?)?.as_ref()
+}
+
+// Returns false if there already is an entry with the id
+fn add_entry<P>(entries: &mut Vec<Option<AssetEntry<P>>>, id: usize, entry: AssetEntry<P>) -> bool {
+    if entries.len() < id {
+        entries.resize_with(id, || None);
+    }
+    if entries[id - 1].is_some() {
+        return false;
+    }
+    entries[id - 1] = Some(entry);
+    true
+}
+
+impl Manifest {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| format!("Failed to read {path}: {e}"))?;
+        let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+        Self::parse(&text, directory).map_err(|msg| format!("{path}:{msg}"))
+    }
+
+    // Paths are relative to `directory`, manifests can be made
+    // in memory (for tests) by passing an empty path
+    pub(crate) fn parse(text: &str, directory: &Path) -> Result<Self, String> {
+        let mut manifest = Self::default();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |msg: String| format!("{}: {msg}", i + 1);
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (kind, id, name, path, properties) = match words.as_slice() {
+                [kind, id, name, path, properties @ ..] => (*kind, *id, *name, *path, properties),
+                _ => {
+                    return Err(error(
+                        "expected `<texture or sprite> <id> <name> <path>`".into(),
+                    ))
+                }
+            };
+
+            let id = match id.parse::<u8>() {
+                Ok(id) if id > 0 => id as usize,
+                _ => return Err(error(format!("invalid id `{id}`, ids go from 1 to 255"))),
+            };
+            let name = name.to_owned();
+            let path = directory.join(path).to_string_lossy().into_owned();
+
+            let added = match kind {
+                "texture" => {
+                    let properties = parse_tile_properties(properties).map_err(error)?;
+                    let entry = AssetEntry {
+                        name,
+                        path,
+                        properties,
+                    };
+                    add_entry(&mut manifest.textures, id, entry)
+                }
+                "sprite" => {
+                    let properties = parse_sprite_properties(properties).map_err(error)?;
+                    let entry = AssetEntry {
+                        name,
+                        path,
+                        properties,
+                    };
+                    add_entry(&mut manifest.sprites, id, entry)
+                }
+                _ => return Err(error(format!("unknown asset type `{kind}`"))),
+            };
+            if !added {
+                return Err(error(format!("{kind} {id} is listed more than once")));
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    pub fn texture(&self, id: u8) -> Option<&AssetEntry<TileProperties>> {
+        get_entry(&self.textures, id)
+    }
+
+    pub fn sprite(&self, id: u8) -> Option<&AssetEntry<SpriteProperties>> {
+        get_entry(&self.sprites, id)
+    }
+
+    // Properties of a tile, None for empty space (tile 0). Tiles
+    // that aren't in the manifest are plain walls
+    pub fn tile(&self, id: u8) -> Option<TileProperties> {
+        if id == 0 {
+            return None;
+        }
+
+        Some(
+            self.texture(id)
+                .map(|entry| entry.properties)
+                .unwrap_or_default(),
+        )
+    }
+
+    // Tiles that block movement
+    pub fn tile_is_solid(&self, id: u8) -> bool {
+        self.tile(id).is_some_and(|tile| tile.solid)
+    }
+
+    // Tiles that can't be seen or shot through
+    pub fn tile_is_opaque(&self, id: u8) -> bool {
+        self.tile(id).is_some_and(|tile| !tile.transparent)
+    }
+
+    pub fn texture_is_sky(&self, id: u8) -> bool {
+        self.texture(id).is_some_and(|entry| entry.properties.sky)
+    }
+
+    // Textures that can be painted as tiles, everything but the skies (ids
+    // without an entry are left out since levels can't use them)
+    pub fn tile_ids(&self) -> Vec<u8> {
+        (1..=self.textures.len() as u8)
+            .filter(|id| self.texture(*id).is_some_and(|entry| !entry.properties.sky))
+            .collect()
+    }
+
+    pub fn sprite_ids(&self) -> Vec<u8> {
+        (1..=self.sprites.len() as u8)
+            .filter(|id| self.sprite(*id).is_some())
+            .collect()
+    }
+
+    pub fn sprite_properties(&self, id: u8) -> SpriteProperties {
+        self.sprite(id)
+            .map(|entry| entry.properties)
+            .unwrap_or_default()
+    }
+
+    // Sprite that is shown on the HUD for a key
+    pub fn key_sprite(&self, key: KeyColor) -> Option<u8> {
+        (1..=self.sprites.len() as u8)
+            .find(|id| self.sprite_properties(*id).pickup == Some(Pickup::Key(key)))
+    }
+
+    // Makes sure that a level only uses textures and sprites from this manifest
+    pub fn check_level(&self, level: &Level) -> Result<(), LevelFileError> {
+        for layer in [TileLayer::Walls, TileLayer::Floor, TileLayer::Ceiling] {
+            let valid = |tile: u8| {
+                tile == 0
+                    || (layer == TileLayer::Ceiling && tile == OPEN_SKY)
+                    || self
+                        .texture(tile)
+                        .is_some_and(|entry| !entry.properties.sky)
+            };
+            if let Some(tile) = level.layer_bytes(layer).iter().find(|tile| !valid(**tile)) {
+                return Err(LevelFileError::InvalidTileType(*tile));
+            }
+        }
+
+        if let Some(sprite) = level
+            .sprites
+            .iter()
+            .find(|sprite| self.sprite(sprite.sprite_type).is_none())
+        {
+            return Err(LevelFileError::InvalidSpriteType(sprite.sprite_type));
+        }
+
+        if level.sky != 0 && !self.texture_is_sky(level.sky) {
+            return Err(LevelFileError::InvalidSkyTexture(level.sky));
+        }
+
+        Ok(())
+    }
+}
+
+// Loads the image for each id, ids that aren't in the manifest or
+// whose image can't be loaded get a placeholder so that the ids
+// after them stay the same
+fn load_images<P>(entries: &[Option<AssetEntry<P>>]) -> Vec<TextureData> {
+    entries
+        .iter()
+        .map(|entry| match entry {
+            Some(entry) => TextureData::load(&entry.path).unwrap_or_else(|msg| {
+                eprintln!("Failed to load {}: {msg}", entry.name);
+                TextureData::placeholder()
+            }),
+            None => TextureData::placeholder(),
+        })
+        .collect()
+}
+
+// Everything that is loaded from a manifest, the images are what
+// the software renderer samples from
+#[derive(Clone)]
+pub struct Assets {
+    pub manifest: Rc<Manifest>,
+    // Indexed by id - 1
+    pub textures: Vec<TextureData>,
+    pub sprites: Vec<TextureData>,
+    pub sprite_defs: Rc<[SpriteDef]>,
+}
+
+impl Assets {
+    pub fn load(manifest_path: &str) -> Result<Self, String> {
+        let manifest = Manifest::load(manifest_path)?;
+        let textures = load_images(&manifest.textures);
+        let sprites = load_images(&manifest.sprites);
+        let sprite_defs = manifest
+            .sprites
+            .iter()
+            .zip(&sprites)
+            .map(|(entry, image)| match entry {
+                Some(entry) => SpriteDef::load(&entry.path, image),
+                None => SpriteDef::single_frame(image),
+            })
+            .collect();
+
+        Ok(Self {
+            manifest: Rc::new(manifest),
+            textures,
+            sprites,
+            sprite_defs,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level_editor::level_file;
+    use crate::sprite::Sprite;
+
+    #[test]
+    fn tiles_are_solid_walls_unless_the_manifest_says_otherwise() {
+        let manifest = Manifest::default();
+        assert!(!manifest.tile_is_solid(0));
+        assert!(manifest.tile_is_solid(1));
+        assert!(manifest.tile_is_opaque(200));
+
+        let manifest = Manifest::parse(
+            "texture 1 bars bars.png passable transparent\n\
+             texture 2 door door.png door red\n\
+             sprite 1 key key.png pickup key red",
+            Path::new(""),
+        )
+        .unwrap();
+        assert!(!manifest.tile_is_solid(1));
+        assert!(!manifest.tile_is_opaque(1));
+        assert!(manifest.tile_is_solid(2));
+        assert!(manifest.tile(2).unwrap().key == Some(KeyColor::Red));
+        assert!(manifest.tile_is_solid(3));
+        assert_eq!(manifest.key_sprite(KeyColor::Red), Some(1));
+    }
+
+    #[test]
+    fn palettes_leave_out_ids_that_arent_in_the_manifest() {
+        let manifest = Manifest::parse(
+            "texture 1 wall wall.png\n\
+             texture 4 wall wall.png\n\
+             sprite 2 barrel barrel.png\n\
+             sprite 3 key key.png pickup key red",
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(manifest.tile_ids(), [1, 4]);
+        assert_eq!(manifest.sprite_ids(), [2, 3]);
+
+        // Everything in the palettes can be saved and loaded again
+        let mut level = Level::new(2, 1);
+        level.set_tile(0, 0, 1);
+        level.set_tile(1, 0, 4);
+        level.place_sprite(Sprite::new(0.5, 0.5, 2));
+        level.place_sprite(Sprite::new(1.5, 0.5, 3));
+        assert!(manifest.check_level(&level).is_ok());
+
+        level.set_tile(1, 0, 2);
+        assert!(matches!(
+            manifest.check_level(&level),
+            Err(LevelFileError::InvalidTileType(2))
+        ));
+        level.set_tile(1, 0, 4);
+        level.place_sprite(Sprite::new(1.5, 0.5, 1));
+        assert!(matches!(
+            manifest.check_level(&level),
+            Err(LevelFileError::InvalidSpriteType(1))
+        ));
+    }
+
+    #[test]
+    fn saving_somewhere_else_keeps_the_same_manifest() {
+        let directory = std::env::temp_dir().join("retro-fps-moved-manifest");
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(directory.join("levels/new")).unwrap();
+        std::fs::write(directory.join("manifest.txt"), "texture 1 wall wall.png").unwrap();
+        let old_path = directory.join("levels/old.lvl");
+        let new_path = directory.join("levels/new/moved.lvl");
+        let (old_path, new_path) = (old_path.to_str().unwrap(), new_path.to_str().unwrap());
+
+        let mut level = Level::new(2, 1);
+        level.manifest = Some("../manifest.txt".to_owned());
+        level_file::write_level_file(&level, old_path).unwrap();
+
+        let mut level = level_file::read_level_file(old_path).unwrap();
+        move_level_manifest(&mut level, old_path, new_path);
+        level_file::write_level_file(&level, new_path).unwrap();
+
+        let moved = level_file::read_level_file(new_path).unwrap();
+        assert_eq!(moved.manifest.as_deref(), Some("../../manifest.txt"));
+        assert!(Manifest::load(&level_manifest_path(&moved, new_path)).is_ok());
+
+        // Levels without their own manifest keep using the default one
+        let mut level = Level::new(2, 1);
+        move_level_manifest(&mut level, old_path, new_path);
+        assert_eq!(level.manifest, None);
+
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for text in [
+            "texture 0 wall wall.png",
+            "texture 1 wall wall.png\ntexture 1 other other.png",
+            "texture 1 wall wall.png bouncy",
+            "model 1 wall wall.png",
+        ] {
+            assert!(Manifest::parse(text, Path::new("")).is_err(), "{text}");
+        }
+    }
+
+    #[test]
+    fn skies_are_only_used_for_open_sky() {
+        let manifest = Manifest::parse(
+            "texture 1 wall wall.png
+             texture 2 sky sky.png sky
+             texture 3 wall wall.png",
+            Path::new(""),
+        )
+        .unwrap();
+        assert_eq!(manifest.tile_ids(), [1, 3]);
+
+        let mut level = Level::new(2, 1);
+        level.set_layer_tile(TileLayer::Ceiling, 0, 0, OPEN_SKY);
+        level.sky = 2;
+        assert!(manifest.check_level(&level).is_ok());
+
+        level.sky = 3;
+        assert!(matches!(
+            manifest.check_level(&level),
+            Err(LevelFileError::InvalidSkyTexture(3))
+        ));
+
+        // Skies can't be painted as tiles, open sky only goes on the ceiling
+        level.sky = 2;
+        for (layer, tile) in [
+            (TileLayer::Ceiling, 2),
+            (TileLayer::Walls, 2),
+            (TileLayer::Floor, OPEN_SKY),
+        ] {
+            let mut level = level.clone();
+            level.set_layer_tile(layer, 1, 0, tile);
+            assert!(matches!(
+                manifest.check_level(&level),
+                Err(LevelFileError::InvalidTileType(bad)) if bad == tile
+            ));
+        }
+    }
+}
@@ -109,6 +109,11 @@ impl MenuElement {
         }
     }
 
+    // Moves and resizes the element, x, y is the new center
+    pub fn set_bounds(&mut self, x: i32, y: i32, w: u32, h: u32) {
+        self.bounding_rect = Rect::from_center(Point::new(x, y), w, h);
+    }
+
     pub fn set_id(&mut self, id: &str) {
         self.id = Some(id.to_owned());
     }
@@ -0,0 +1,157 @@
+use crate::level::Level;
+use crate::manifest::Manifest;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+// Stop searching after looking at this many tiles so
+// that an unreachable goal can't take too long
+const MAX_SEARCH_NODES: usize = 4096;
+// Costs of moving straight and diagonally (10 * sqrt(2))
+const STRAIGHT_COST: u32 = 10;
+const DIAGONAL_COST: u32 = 14;
+
+pub fn walkable(level: &Level, manifest: &Manifest, x: isize, y: isize) -> bool {
+    !level.out_of_bounds(x, y) && !manifest.tile_is_solid(level.get_tile(x, y))
+}
+
+// Estimated cost from one tile to another, assuming there are no walls
+fn heuristic(from: (isize, isize), to: (isize, isize)) -> u32 {
+    let dx = (from.0 - to.0).unsigned_abs() as u32;
+    let dy = (from.1 - to.1).unsigned_abs() as u32;
+    STRAIGHT_COST * dx.max(dy) + (DIAGONAL_COST - STRAIGHT_COST) * dx.min(dy)
+}
+
+// A* search over the tiles of the level, returns the tiles to move through
+// to get from `start` to `goal` (not including `start`), or None if there is
+// no path. Diagonal moves are allowed as long as they don't cut a corner.
+pub fn find_path(
+    level: &Level,
+    manifest: &Manifest,
+    start: (isize, isize),
+    goal: (isize, isize),
+) -> Option<Vec<(isize, isize)>> {
+    if !walkable(level, manifest, goal.0, goal.1) {
+        return None;
+    }
+
+    let mut open = BinaryHeap::new();
+    let mut came_from = HashMap::new();
+    let mut cost_so_far = HashMap::new();
+
+    open.push(Reverse((heuristic(start, goal), start)));
+    cost_so_far.insert(start, 0u32);
+
+    let mut searched = 0;
+    while let Some(Reverse((_, current))) = open.pop() {
+        if current == goal {
+            // Walk back from the goal to get the path
+            let mut path = vec![current];
+            let mut tile = current;
+            while let Some(&prev) = came_from.get(&tile) {
+                if prev == start {
+                    break;
+                }
+                path.push(prev);
+                tile = prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        searched += 1;
+        if searched > MAX_SEARCH_NODES {
+            return None;
+        }
+
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                if dx == 0 && dy == 0 {
+                    continue;
+                }
+
+                let (nx, ny) = (current.0 + dx, current.1 + dy);
+                if !walkable(level, manifest, nx, ny) {
+                    continue;
+                }
+
+                let diagonal = dx != 0 && dy != 0;
+                if diagonal
+                    && (!walkable(level, manifest, current.0 + dx, current.1)
+                        || !walkable(level, manifest, current.0, current.1 + dy))
+                {
+                    continue;
+                }
+
+                let step = if diagonal {
+                    DIAGONAL_COST
+                } else {
+                    STRAIGHT_COST
+                };
+                let new_cost = cost_so_far[&current] + step;
+                let next = (nx, ny);
+                if cost_so_far
+                    .get(&next)
+                    .map(|&cost| new_cost < cost)
+                    .unwrap_or(true)
+                {
+                    cost_so_far.insert(next, new_cost);
+                    came_from.insert(next, current);
+                    open.push(Reverse((new_cost + heuristic(next, goal), next)));
+                }
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::level::TileLayer;
+
+    #[test]
+    fn no_path_to_a_wall() {
+        let mut level = Level::new(5, 5);
+        level.set_tile(4, 4, 1);
+        let manifest = Manifest::default();
+        assert_eq!(find_path(&level, &manifest, (0, 0), (4, 4)), None);
+        let path = find_path(&level, &manifest, (0, 0), (3, 4)).unwrap();
+        assert_eq!((path.len(), path.last()), (4, Some(&(3, 4))));
+    }
+
+    #[test]
+    fn diagonal_moves_dont_cut_corners() {
+        // . #
+        // . .
+        let mut level = Level::new(2, 2);
+        level.set_tile(1, 0, 1);
+        let manifest = Manifest::default();
+        assert_eq!(
+            find_path(&level, &manifest, (0, 0), (1, 1)),
+            Some(vec![(0, 1), (1, 1)])
+        );
+
+        // Squeezing between two walls that touch at a corner isn't allowed either
+        level.set_tile(0, 1, 1);
+        assert_eq!(find_path(&level, &manifest, (0, 0), (1, 1)), None);
+    }
+
+    #[test]
+    fn gives_up_on_unreachable_goal() {
+        // A big open level with the goal walled in, the search would have
+        // to look at every open tile to find out there's no way in
+        let mut level = Level::new(128, 128);
+        for (x, y) in [(125, 125), (126, 125), (127, 125), (125, 126), (125, 127)] {
+            level.set_tile(x, y, 1);
+        }
+        let manifest = Manifest::default();
+        let open_tiles = level
+            .layer_bytes(TileLayer::Walls)
+            .iter()
+            .filter(|&&tile| tile == 0)
+            .count();
+        assert!(open_tiles > MAX_SEARCH_NODES);
+        assert_eq!(find_path(&level, &manifest, (0, 0), (126, 126)), None);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::weapon::{Weapon, WEAPONS};
+
+const STARTING_AMMO: u32 = 20;
+const MAX_AMMO: u32 = 200;
+const MAX_HEALTH: f64 = 100.0;
+const PLAYER_RADIUS: f64 = 0.25;
+const STARTING_ARMOR: f64 = 50.0;
+// How much of the damage taken is soaked up by armor (while there is any left)
+const ARMOR_ABSORPTION: f64 = 1.0 / 3.0;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum KeyColor {
+    Red,
+    Blue,
+    Green,
+}
+
+impl KeyColor {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "red" => Some(Self::Red),
+            "blue" => Some(Self::Blue),
+            "green" => Some(Self::Green),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Red => "red",
+            Self::Blue => "blue",
+            Self::Green => "green",
+        }
+    }
+}
+
+pub struct Player {
+    // Size of the player when colliding with walls and solid sprites
+    pub radius: f64,
+    pub health: f64,
+    pub armor: f64,
+    pub keys: Vec<KeyColor>,
+    pub ammo: u32,
+    // Index into WEAPONS
+    pub weapon: usize,
+    // Time until the weapon can be fired again (in seconds)
+    pub fire_timer: f64,
+}
+
+impl Player {
+    pub fn new() -> Self {
+        Self {
+            radius: PLAYER_RADIUS,
+            health: MAX_HEALTH,
+            armor: STARTING_ARMOR,
+            keys: vec![],
+            ammo: STARTING_AMMO,
+            weapon: 0,
+            fire_timer: 0.0,
+        }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.health <= 0.0
+    }
+
+    // Armor takes some of the damage until it runs out
+    pub fn take_damage(&mut self, damage: f64) {
+        let absorbed = (damage * ARMOR_ABSORPTION).min(self.armor);
+        self.armor -= absorbed;
+        self.health = (self.health - (damage - absorbed)).max(0.0);
+    }
+
+    // Returns false if the player is already at full health
+    pub fn give_health(&mut self, amount: f64) -> bool {
+        if self.health >= MAX_HEALTH {
+            return false;
+        }
+
+        self.health = (self.health + amount).min(MAX_HEALTH);
+        true
+    }
+
+    // Brings the player back to life, keys and weapons are kept
+    pub fn respawn(&mut self) {
+        self.health = MAX_HEALTH;
+        self.armor = STARTING_ARMOR;
+        self.ammo = self.ammo.max(STARTING_AMMO);
+        self.fire_timer = 0.0;
+    }
+
+    pub fn weapon(&self) -> &'static Weapon {
+        &WEAPONS[self.weapon]
+    }
+
+    // Returns false if the player already has as much ammo as they can carry
+    pub fn give_ammo(&mut self, amount: u32) -> bool {
+        if self.ammo >= MAX_AMMO {
+            return false;
+        }
+
+        self.ammo = (self.ammo + amount).min(MAX_AMMO);
+        true
+    }
+
+    pub fn has_key(&self, key: KeyColor) -> bool {
+        self.keys.contains(&key)
+    }
+
+    pub fn give_key(&mut self, key: KeyColor) {
+        if !self.has_key(key) {
+            self.keys.push(key);
+        }
+    }
+}
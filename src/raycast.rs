@@ -1,118 +1,410 @@
-use crate::level::Level;
+use crate::door::Doors;
+use crate::level::{Level, TileLayer};
+use crate::manifest::Manifest;
 use crate::sprite::{dist, new_vector2, Vector2f64};
 
-fn scan_vertical_positive(
-    start: &Vector2f64,
-    angle: f64,
-    max_dist: f64,
-    level: &Level,
-) -> (Vector2f64, u8) {
-    let mut rayx = start.x.ceil();
-    let mut rayy = (rayx - start.x) * angle.tan() + start.y;
-    while (start.x - rayx).abs() < max_dist {
-        let xind = rayx as isize;
-        let yind = rayy.floor() as isize;
-
-        if !level.out_of_bounds(xind, yind) && level.get_tile(xind, yind) != 0 {
-            return (new_vector2(rayx, rayy), level.get_tile(xind, yind));
+// Side of a tile that a ray hit, named after the direction it faces (north is -y)
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Face {
+    North,
+    South,
+    East,
+    West,
+}
+
+impl Face {
+    pub fn normal(&self) -> Vector2f64 {
+        match self {
+            Self::North => new_vector2(0.0, -1.0),
+            Self::South => new_vector2(0.0, 1.0),
+            Self::East => new_vector2(1.0, 0.0),
+            Self::West => new_vector2(-1.0, 0.0),
         }
+    }
 
-        rayx += 1.0;
-        rayy += angle.tan();
+    // East and west faces run along the y axis
+    pub fn is_east_west(&self) -> bool {
+        matches!(self, Self::East | Self::West)
     }
 
-    (new_vector2(rayx, rayy), 0)
+    // The face that is closest to facing in the direction of a normal
+    fn from_normal(normal: &Vector2f64) -> Self {
+        match (
+            normal.x.abs() >= normal.y.abs(),
+            normal.x > 0.0,
+            normal.y > 0.0,
+        ) {
+            (true, true, _) => Self::East,
+            (true, false, _) => Self::West,
+            (false, _, true) => Self::South,
+            (false, _, false) => Self::North,
+        }
+    }
 }
 
-fn scan_vertical_negative(
-    start: &Vector2f64,
-    angle: f64,
-    max_dist: f64,
-    level: &Level,
-) -> (Vector2f64, u8) {
-    let mut rayx = start.x.floor();
-    let mut rayy = (rayx - start.x) * angle.tan() + start.y;
-    while (start.x - rayx).abs() < max_dist {
-        let xind = rayx as isize - 1;
-        let yind = rayy.floor() as isize;
-
-        if !level.out_of_bounds(xind, yind) && level.get_tile(xind, yind) != 0 {
-            return (new_vector2(rayx, rayy), level.get_tile(xind, yind));
+pub struct Ray {
+    pub start: Vector2f64,
+    pub angle: f64,
+    pub max_dist: f64,
+    // Direction that the perpendicular distance is measured in (the way the
+    // camera is facing), the same as the angle unless it is changed
+    pub view_angle: f64,
+}
+
+impl Ray {
+    pub fn new(start: Vector2f64, angle: f64, max_dist: f64) -> Self {
+        Self {
+            start,
+            angle,
+            max_dist,
+            view_angle: angle,
         }
+    }
 
-        rayx -= 1.0;
-        rayy -= angle.tan();
+    pub fn viewed_from(self, view_angle: f64) -> Self {
+        Self { view_angle, ..self }
     }
+}
 
-    (new_vector2(rayx, rayy), 0)
+pub struct RaycastHit {
+    pub tile: u8,
+    // Tile that was hit
+    pub x: isize,
+    pub y: isize,
+    pub face: Face,
+    pub pos: Vector2f64,
+    // Distance along the ray
+    pub distance: f64,
+    // Distance in the ray's view direction, walls are drawn with this
+    // so that they don't look curved
+    pub perp_distance: f64,
+    // Where across the face the ray hit, from 0 to 1
+    pub texture_u: f64,
 }
 
-fn scan_horizontal_positive(
-    start: &Vector2f64,
-    angle: f64,
-    max_dist: f64,
+// Walks through the tiles that a ray passes through one at a time (DDA) and
+// calls `on_hit` for each one that isn't empty until it returns true or the
+// ray goes past its max distance. Doors are in the middle of their tile so
+// they are only hit if the ray reaches the door, the same goes for tiles that
+// are a segment. Next to each other tiles of the same type and height are
+// treated as one block so only the first one is hit
+fn walk(
+    ray: &Ray,
     level: &Level,
-) -> (Vector2f64, u8) {
-    let mut rayy = start.y.ceil();
-    let mut rayx = (rayy - start.y) * 1.0 / angle.tan() + start.x;
-    while (start.y - rayy).abs() < max_dist {
-        let xind = rayx.floor() as isize;
-        let yind = rayy as isize;
-
-        if !level.out_of_bounds(xind, yind) && level.get_tile(xind, yind) != 0 {
-            return (new_vector2(rayx, rayy), level.get_tile(xind, yind));
+    doors: &Doors,
+    manifest: &Manifest,
+    mut on_hit: impl FnMut(RaycastHit) -> bool,
+) {
+    let start = &ray.start;
+    let (dir_x, dir_y) = (ray.angle.cos(), ray.angle.sin());
+    let (mut x, mut y) = (start.x.floor() as isize, start.y.floor() as isize);
+
+    // Distance along the ray between crossing one grid line and the next
+    // on each axis, and the distance to the first grid line that is crossed
+    let (delta_x, delta_y) = (1.0 / dir_x.abs(), 1.0 / dir_y.abs());
+    let step_x = if dir_x < 0.0 { -1 } else { 1 };
+    let step_y = if dir_y < 0.0 { -1 } else { 1 };
+    let mut side_x = match dir_x {
+        dir if dir < 0.0 => (start.x - x as f64) * delta_x,
+        dir if dir > 0.0 => (x as f64 + 1.0 - start.x) * delta_x,
+        _ => f64::INFINITY,
+    };
+    let mut side_y = match dir_y {
+        dir if dir < 0.0 => (start.y - y as f64) * delta_y,
+        dir if dir > 0.0 => (y as f64 + 1.0 - start.y) * delta_y,
+        _ => f64::INFINITY,
+    };
+
+    let hit = |tile, x, y, face: Face, distance: f64, texture_u| RaycastHit {
+        tile,
+        x,
+        y,
+        face,
+        pos: new_vector2(start.x + dir_x * distance, start.y + dir_y * distance),
+        distance,
+        perp_distance: distance * (ray.angle - ray.view_angle).cos(),
+        texture_u,
+    };
+
+    let segment_hit = |tile, x, y| {
+        let segment = manifest.tile(tile)?.segment?;
+        let (distance, u) = segment
+            .intersect(x, y, start, ray.angle)
+            .filter(|(distance, _)| *distance <= ray.max_dist)?;
+        let face = Face::from_normal(&segment.normal(ray.angle));
+        Some(hit(tile, x, y, face, distance, u))
+    };
+
+    // The ray can start next to the segment in its tile
+    let block = |x, y| {
+        (
+            level.get_tile(x, y),
+            level.get_layer_tile(TileLayer::Height, x, y),
+        )
+    };
+    let mut previous = block(x, y);
+    if let Some(hit) = segment_hit(previous.0, x, y) {
+        if on_hit(hit) {
+            return;
+        }
+    }
+
+    loop {
+        let (distance, face) = if side_x < side_y {
+            x += step_x;
+            side_x += delta_x;
+            (
+                side_x - delta_x,
+                if step_x > 0 { Face::West } else { Face::East },
+            )
+        } else {
+            y += step_y;
+            side_y += delta_y;
+            (
+                side_y - delta_y,
+                if step_y > 0 { Face::North } else { Face::South },
+            )
+        };
+
+        if distance > ray.max_dist || level.out_of_bounds(x, y) {
+            return;
         }
 
-        rayy += 1.0;
-        rayx += 1.0 / angle.tan();
+        let same_block = block(x, y) == previous;
+        previous = block(x, y);
+        let tile = previous.0;
+
+        if let Some(door) = doors.get(x, y) {
+            let door_hit = door
+                .intersect(x, y, start, ray.angle)
+                .filter(|d| *d <= ray.max_dist);
+            if let Some(d) = door_hit {
+                let face = match (door.vertical(), step_x > 0, step_y > 0) {
+                    (true, true, _) => Face::West,
+                    (true, false, _) => Face::East,
+                    (false, _, true) => Face::North,
+                    (false, _, false) => Face::South,
+                };
+                let pos = new_vector2(start.x + dir_x * d, start.y + dir_y * d);
+                if on_hit(hit(door.tile, x, y, face, d, door.texture_u(&pos))) {
+                    return;
+                }
+            }
+            continue;
+        }
+
+        if manifest
+            .tile(tile)
+            .is_some_and(|tile| tile.segment.is_some())
+        {
+            if let Some(hit) = segment_hit(tile, x, y) {
+                if on_hit(hit) {
+                    return;
+                }
+            }
+            continue;
+        }
+
+        if tile != 0 && !same_block {
+            let texture_u = if face.is_east_west() {
+                (start.y + dir_y * distance).fract()
+            } else {
+                (start.x + dir_x * distance).fract()
+            };
+            if on_hit(hit(tile, x, y, face, distance, texture_u)) {
+                return;
+            }
+        }
     }
+}
 
-    (new_vector2(rayx, rayy), 0)
+// Returns the first tile that `stops` the ray, None if nothing
+// is hit within the ray's max distance
+pub fn raycast(
+    ray: &Ray,
+    level: &Level,
+    doors: &Doors,
+    manifest: &Manifest,
+    stops: impl Fn(u8) -> bool,
+) -> Option<RaycastHit> {
+    let mut result = None;
+    walk(ray, level, doors, manifest, |hit| {
+        if stops(hit.tile) {
+            result = Some(hit);
+        }
+        result.is_some()
+    });
+    result
 }
 
-fn scan_horizontal_negative(
+// Returns every tile that the ray hits up to and including the first
+// one that can't be seen past, closest first
+pub fn raycast_all(
+    ray: &Ray,
+    level: &Level,
+    doors: &Doors,
+    manifest: &Manifest,
+    mut see_through: impl FnMut(&RaycastHit) -> bool,
+) -> Vec<RaycastHit> {
+    let mut hits = vec![];
+    walk(ray, level, doors, manifest, |hit| {
+        let stop = !see_through(&hit);
+        hits.push(hit);
+        stop
+    });
+    hits
+}
+
+// Checks if a ray passes within `radius` of a sprite, returns how
+// far along the ray the sprite was hit (None if it was missed)
+pub fn ray_hits_sprite(
     start: &Vector2f64,
     angle: f64,
-    max_dist: f64,
+    sprite_pos: &Vector2f64,
+    radius: f64,
+) -> Option<f64> {
+    let to_sprite_x = sprite_pos.x - start.x;
+    let to_sprite_y = sprite_pos.y - start.y;
+    // Distance along the ray to the point closest to the sprite
+    let along = to_sprite_x * angle.cos() + to_sprite_y * angle.sin();
+    if along < 0.0 {
+        return None;
+    }
+
+    let closest_sq = to_sprite_x.powi(2) + to_sprite_y.powi(2) - along.powi(2);
+    if closest_sq > radius.powi(2) {
+        return None;
+    }
+
+    Some((along - (radius.powi(2) - closest_sq).sqrt()).max(0.0))
+}
+
+// Returns true if there are no walls between two points that can't be seen through
+pub fn line_of_sight(
     level: &Level,
-) -> (Vector2f64, u8) {
-    let mut rayy = start.y.floor();
-    let mut rayx = (rayy - start.y) * 1.0 / angle.tan() + start.x;
-    while (start.y - rayy).abs() < max_dist {
-        let xind = rayx.floor() as isize;
-        let yind = rayy as isize - 1;
-
-        if !level.out_of_bounds(xind, yind) && level.get_tile(xind, yind) != 0 {
-            return (new_vector2(rayx, rayy), level.get_tile(xind, yind));
+    manifest: &Manifest,
+    doors: &Doors,
+    from: &Vector2f64,
+    to: &Vector2f64,
+) -> bool {
+    let distance = dist(from, to);
+    let angle = (to.y - from.y).atan2(to.x - from.x);
+    let ray = Ray::new(*from, angle, distance);
+    raycast(&ray, level, doors, manifest, |tile| {
+        manifest.tile_is_opaque(tile)
+    })
+    .is_none()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::{FRAC_PI_2, PI};
+    use std::path::Path;
+
+    // A room with walls around the edge
+    fn walled_box(w: u32, h: u32) -> Level {
+        let mut level = Level::new(w, h);
+        for x in 0..w as isize {
+            level.set_tile(x, 0, 1);
+            level.set_tile(x, h as isize - 1, 1);
         }
+        for y in 0..h as isize {
+            level.set_tile(0, y, 1);
+            level.set_tile(w as isize - 1, y, 1);
+        }
+        level
+    }
 
-        rayy -= 1.0;
-        rayx -= 1.0 / angle.tan();
+    fn cast(level: &Level, doors: &Doors, manifest: &Manifest, ray: &Ray) -> RaycastHit {
+        raycast(ray, level, doors, manifest, |tile| tile != 0).expect("the ray hit nothing")
     }
 
-    (new_vector2(rayx, rayy), 0)
-}
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{a} != {b}");
+    }
 
-//Returns x, y, and tile type
-pub fn raycast(start: &Vector2f64, angle: f64, max_dist: f64, level: &Level) -> (Vector2f64, u8) {
-    //Check vertical lines
-    let vert = if angle.cos() > 0.0 {
-        scan_vertical_positive(start, angle, max_dist, level)
-    } else {
-        scan_vertical_negative(start, angle, max_dist, level)
-    };
+    #[test]
+    fn rays_along_the_axes_hit_the_walls_of_a_box() {
+        let level = walled_box(5, 5);
+        let manifest = Manifest::default();
+        let start = new_vector2(2.25, 2.75);
 
-    //Check horizontal lines
-    let horiz = if angle.sin() > 0.0 {
-        scan_horizontal_positive(start, angle, max_dist, level)
-    } else {
-        scan_horizontal_negative(start, angle, max_dist, level)
-    };
+        // Angle, face, distance, texture u
+        for (angle, face, distance, u) in [
+            (0.0, Face::West, 1.75, 0.75),
+            (FRAC_PI_2, Face::North, 1.25, 0.25),
+            (PI, Face::East, 1.25, 0.75),
+            (3.0 * FRAC_PI_2, Face::South, 1.75, 0.25),
+        ] {
+            let ray = Ray::new(start, angle, 10.0);
+            let hit = cast(&level, &Doors::default(), &manifest, &ray);
+            assert_eq!(hit.face, face);
+            assert_close(hit.distance, distance);
+            assert_close(hit.perp_distance, distance);
+            assert_close(hit.texture_u, u);
+
+            // Looking off to the side the wall is closer in the view direction
+            let ray = Ray::new(start, angle, 10.0).viewed_from(angle + 0.5);
+            let hit = cast(&level, &Doors::default(), &manifest, &ray);
+            assert_close(hit.distance, distance);
+            assert_close(hit.perp_distance, distance * 0.5f64.cos());
+        }
+
+        // Walls past the max distance aren't hit
+        let ray = Ray::new(start, 0.0, 1.5);
+        assert!(raycast(&ray, &level, &Doors::default(), &manifest, |tile| tile != 0).is_none());
+    }
+
+    #[test]
+    fn ray_starting_in_a_segment_tile_hits_the_segment() {
+        let mut level = walled_box(5, 5);
+        level.set_tile(2, 2, 2);
+        let manifest =
+            Manifest::parse("texture 2 fence fence.png segment 0 0 1 1", Path::new("")).unwrap();
+        let start = new_vector2(2.75, 2.25);
+
+        let hit = cast(
+            &level,
+            &Doors::default(),
+            &manifest,
+            &Ray::new(start, PI, 10.0),
+        );
+        assert_eq!((hit.tile, hit.x, hit.y, hit.face), (2, 2, 2, Face::East));
+        assert_close(hit.distance, 0.5);
+        assert_close(hit.texture_u, 0.25);
+
+        // The segment is behind a ray going the other way
+        let hit = cast(
+            &level,
+            &Doors::default(),
+            &manifest,
+            &Ray::new(start, 0.0, 10.0),
+        );
+        assert_eq!((hit.tile, hit.x, hit.face), (1, 4, Face::West));
+        assert_close(hit.distance, 1.25);
+    }
+
+    #[test]
+    fn half_open_door_is_only_hit_where_it_is_still_closed() {
+        // A corridor with a door in the middle of it
+        let mut level = walled_box(5, 3);
+        level.set_tile(2, 1, 2);
+        let manifest = Manifest::parse("texture 2 door door.png door", Path::new("")).unwrap();
+        let mut doors = Doors::new(&level, &manifest);
+        doors.open(2, 1);
+        doors.update(&mut level, &[], 1.0 / 3.0);
+
+        let ray = Ray::new(new_vector2(1.5, 1.75), 0.0, 10.0);
+        let hit = cast(&level, &doors, &manifest, &ray);
+        assert_eq!((hit.tile, hit.x, hit.face), (2, 2, Face::West));
+        assert_close(hit.distance, 1.0);
+        assert_close(hit.texture_u, 0.25);
 
-    //Return the value that is closest
-    if (dist(&horiz.0, start) < dist(&vert.0, start) && horiz.1 != 0) || vert.1 == 0 {
-        horiz
-    } else {
-        vert
+        // The half that has slid into the wall lets the ray through
+        let ray = Ray::new(new_vector2(1.5, 1.25), 0.0, 10.0);
+        let hit = cast(&level, &doors, &manifest, &ray);
+        assert_eq!((hit.tile, hit.x, hit.face), (1, 4, Face::West));
+        assert_close(hit.distance, 2.5);
     }
 }
@@ -0,0 +1,73 @@
+use crate::sprite::{new_vector2, Vector2f64};
+
+// A wall that is a line inside of its tile instead of filling the whole tile,
+// the points go from (0, 0) at the top left corner of the tile to (1, 1)
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub start: Vector2f64,
+    pub end: Vector2f64,
+}
+
+fn cross(a: &Vector2f64, b: &Vector2f64) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+impl Segment {
+    // Ends of the segment in the level when it is in the tile at (x, y)
+    fn placed(&self, x: isize, y: isize) -> (Vector2f64, Vector2f64) {
+        let (x, y) = (x as f64, y as f64);
+        (
+            new_vector2(x + self.start.x, y + self.start.y),
+            new_vector2(x + self.end.x, y + self.end.y),
+        )
+    }
+
+    // Returns how far along a ray the segment in the tile at (x, y) is hit and
+    // how far along the segment it was hit (from 0 to 1), None if it is missed
+    pub fn intersect(
+        &self,
+        x: isize,
+        y: isize,
+        start: &Vector2f64,
+        angle: f64,
+    ) -> Option<(f64, f64)> {
+        let (a, b) = self.placed(x, y);
+        let dir = new_vector2(angle.cos(), angle.sin());
+        let along = new_vector2(b.x - a.x, b.y - a.y);
+
+        // Rays going the same way as the segment never hit it
+        let denominator = cross(&dir, &along);
+        if denominator.abs() < 1e-9 {
+            return None;
+        }
+
+        let to_start = new_vector2(a.x - start.x, a.y - start.y);
+        let distance = cross(&to_start, &along) / denominator;
+        let u = cross(&to_start, &dir) / denominator;
+        if distance < 0.0 || !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        Some((distance, u))
+    }
+
+    // Normal of the side of the segment that faces a ray going in `angle`
+    pub fn normal(&self, angle: f64) -> Vector2f64 {
+        let (dx, dy) = (self.end.x - self.start.x, self.end.y - self.start.y);
+        let length = (dx * dx + dy * dy).sqrt();
+        let (nx, ny) = (-dy / length, dx / length);
+        if nx * angle.cos() + ny * angle.sin() > 0.0 {
+            new_vector2(-nx, -ny)
+        } else {
+            new_vector2(nx, ny)
+        }
+    }
+
+    // Closest point on the segment in the tile at (x, y) to a point
+    pub fn closest_point(&self, x: isize, y: isize, pos: &Vector2f64) -> Vector2f64 {
+        let (a, b) = self.placed(x, y);
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let t = (((pos.x - a.x) * dx + (pos.y - a.y) * dy) / (dx * dx + dy * dy)).clamp(0.0, 1.0);
+        new_vector2(a.x + dx * t, a.y + dy * t)
+    }
+}
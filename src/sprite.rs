@@ -1,10 +1,12 @@
+use crate::animation::{AnimationState, SpriteDef};
+use crate::enemy::Enemy;
+use crate::framebuffer::{FrameBuffer, TextureData};
+use crate::game::lighting;
 use crate::Camera;
-use sdl2::{
-    rect::{Point, Rect},
-    render::{Canvas, Texture},
-    video::Window,
-};
+use crate::Level;
+use sdl2::rect::{Point, Rect};
 
+#[derive(Clone, Copy)]
 pub struct Vector2f64 {
     pub x: f64,
     pub y: f64,
@@ -18,9 +20,19 @@ pub fn dist(v1: &Vector2f64, v2: &Vector2f64) -> f64 {
     ((v1.x - v2.x).powi(2) + (v2.y - v1.y).powi(2)).sqrt()
 }
 
+#[derive(Clone)]
 pub struct Sprite {
     pub pos: Vector2f64,
     pub sprite_type: u8,
+    // Only set for enemies while the level is being played
+    pub enemy: Option<Enemy>,
+    // Only set for sprites that can be shot while the level is being played
+    pub health: Option<f64>,
+    // Time until a barrel explodes, set once it has been set off
+    pub fuse: Option<f64>,
+    // Direction the sprite is looking in (in radians)
+    pub facing: f64,
+    pub animation: AnimationState,
 }
 
 impl Sprite {
@@ -28,66 +40,83 @@ impl Sprite {
         Self {
             pos: new_vector2(x, y),
             sprite_type: spr_type,
+            enemy: None,
+            health: None,
+            fuse: None,
+            facing: 0.0,
+            animation: AnimationState::new(),
         }
     }
 
-    pub fn display(
+    // How far in front of the camera the sprite is
+    pub fn view_depth(&self, cam: &Camera) -> f64 {
+        let sprite_trans_x = self.pos.x - cam.position.x;
+        let sprite_trans_y = self.pos.y - cam.position.y;
+        sprite_trans_x * (-cam.rotation).cos() - sprite_trans_y * (-cam.rotation).sin()
+    }
+
+    // Draws the sprite into a framebuffer, the width of each
+    // column is worked out from the size of the depth buffer
+    pub fn render(
         &self,
-        canvas: &mut Canvas<Window>,
+        framebuffer: &mut FrameBuffer,
         depth_buffer: &[f64],
         cam: &Camera,
-        sprite_image: &Texture,
-    ) -> Result<(), String> {
+        level: &Level,
+        sprite_image: &TextureData,
+        def: &SpriteDef,
+    ) {
+        let (view_w, view_h) = (framebuffer.width() as f64, framebuffer.height() as f64);
+        let column_width = (framebuffer.width() / depth_buffer.len() as u32) as i32;
+
         let sprite_trans_x = self.pos.x - cam.position.x;
         let sprite_trans_y = self.pos.y - cam.position.y;
-        let sprite_rotated_y =
-            sprite_trans_x * (-cam.rotation).cos() - sprite_trans_y * (-cam.rotation).sin();
+        let sprite_rotated_y = self.view_depth(cam);
         let sprite_rotated_x =
             sprite_trans_x * (-cam.rotation).sin() + sprite_trans_y * (-cam.rotation).cos();
 
         if sprite_rotated_y < 0.4 {
-            return Ok(());
+            return;
         }
 
-        let sprite_sz = 400.0;
+        // Sprites are lit the same way as the walls around them
+        let (color_mod, fog) = lighting(level, self.pos.x, self.pos.y, sprite_rotated_y, 1.0);
 
-        let sprite_screen_size = (sprite_sz / sprite_rotated_y) as u32;
-        let sprite_screen_y =
-            (320.0 / sprite_rotated_y + 320.0 - sprite_screen_size as f64 / 2.0) as i32;
-        let norm_x = (sprite_rotated_x / sprite_rotated_y).atan() / cam.fov + 0.5;
-
-        let sprite_screen_x = (norm_x * 800.0) as i32;
+        // Which way the camera is looking at the sprite from picks the frame for
+        // sprites with directions
+        let view_angle = (-sprite_trans_y).atan2(-sprite_trans_x) - self.facing;
+        let frame = def.frame_rect(&self.animation, view_angle);
 
-        let fov_range = 2.0 * (cam.fov / 2.0).tan() * sprite_rotated_y;
-        let sprite_start_x = ((sprite_rotated_x - sprite_sz / 640.0) / fov_range) + 0.5;
-        let sprite_end_x = ((sprite_rotated_x + sprite_sz / 640.0) / fov_range) + 0.5;
+        let screen_h = def.height * view_h / sprite_rotated_y;
+        let screen_w = screen_h * frame.width() as f64 / frame.height() as f64;
+        // The bottom of the sprite is on the floor
+        let floor_y = view_h / 2.0 / sprite_rotated_y + view_h / 2.0;
+        let sprite_screen_y = (floor_y - screen_h / 2.0) as i32;
+        let norm_x = (sprite_rotated_x / sprite_rotated_y).atan() / cam.fov + 0.5;
+        let sprite_screen_x = (norm_x * view_w) as i32;
 
-        if ((sprite_start_x < 1.0 && sprite_end_x > 0.0)
-            || (sprite_end_x > 1.0 && sprite_start_x < 0.0))
-            && sprite_rotated_y > 0.0
-        {
-            let startx = (sprite_screen_x - sprite_screen_size as i32 / 2) / 2;
-            let endx = (sprite_screen_x + sprite_screen_size as i32 / 2) / 2;
-            let mut pixel_x = 0.0f64;
-            for i in startx..endx {
-                if i >= 0
-                    && (i as usize) < depth_buffer.len()
-                    && depth_buffer[i as usize] > sprite_rotated_y
-                {
-                    canvas.copy(
-                        sprite_image,
-                        Rect::new(pixel_x as i32, 0, 1, 16),
-                        Rect::from_center(
-                            Point::new(i * 2 + 1 + 80, sprite_screen_y),
-                            2,
-                            sprite_screen_size,
-                        ),
-                    )?;
-                }
-                pixel_x += 16.0 / sprite_screen_size as f64 * 2.0;
+        let startx = (sprite_screen_x - screen_w as i32 / 2) / column_width;
+        let endx = (sprite_screen_x + screen_w as i32 / 2) / column_width;
+        let mut pixel_x = 0.0f64;
+        for i in startx..endx {
+            if i >= 0
+                && (i as usize) < depth_buffer.len()
+                && depth_buffer[i as usize] > sprite_rotated_y
+            {
+                let src_x = frame.x() + (pixel_x as i32).min(frame.width() as i32 - 1);
+                framebuffer.copy_column_part(
+                    sprite_image,
+                    Rect::new(src_x, frame.y(), 1, frame.height()),
+                    Rect::from_center(
+                        Point::new(i * column_width + column_width / 2, sprite_screen_y),
+                        column_width as u32,
+                        screen_h as u32,
+                    ),
+                    color_mod,
+                    fog,
+                );
             }
+            pixel_x += frame.width() as f64 / screen_w * column_width as f64;
         }
-
-        Ok(())
     }
 }
@@ -0,0 +1,67 @@
+use sdl2::rect::{Point, Rect};
+
+// Width divided by height of the game view, the field of view is horizontal
+// so the view keeps this shape and gets black bars around it instead of
+// getting stretched
+const ASPECT_RATIO: f64 = 5.0 / 4.0;
+// Limits on the render scale so that a bad setting can't make the
+// framebuffer empty or huge
+const MIN_RENDER_SCALE: f64 = 0.05;
+const MAX_RENDER_SCALE: f64 = 2.0;
+
+// Part of the window that the game is drawn in
+#[derive(Clone, Copy)]
+pub struct Viewport {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+    // Resolution that the game is rendered at compared to the size of the
+    // viewport, anything below 1 gives a more pixelated look
+    pub render_scale: f64,
+}
+
+impl Viewport {
+    // Largest viewport that fits in the window, centered in it
+    pub fn fit(window_w: u32, window_h: u32, render_scale: f64) -> Self {
+        let (width, height) = if window_w as f64 > window_h as f64 * ASPECT_RATIO {
+            ((window_h as f64 * ASPECT_RATIO) as u32, window_h)
+        } else {
+            (window_w, (window_w as f64 / ASPECT_RATIO) as u32)
+        };
+
+        Self {
+            x: (window_w - width) as i32 / 2,
+            y: (window_h - height) as i32 / 2,
+            width,
+            height,
+            render_scale,
+        }
+    }
+
+    pub fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.width.max(1), self.height.max(1))
+    }
+
+    pub fn center(&self) -> Point {
+        self.rect().center()
+    }
+
+    pub fn right(&self) -> i32 {
+        self.x + self.width as i32
+    }
+
+    pub fn bottom(&self) -> i32 {
+        self.y + self.height as i32
+    }
+
+    // Size of the image that the game gets rendered into before it
+    // is stretched over the viewport
+    pub fn render_size(&self) -> (u32, u32) {
+        let scale = self.render_scale.clamp(MIN_RENDER_SCALE, MAX_RENDER_SCALE);
+        (
+            ((self.width as f64 * scale) as u32).max(1),
+            ((self.height as f64 * scale) as u32).max(1),
+        )
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Weapon {
+    pub name: &'static str,
+    // Damage done by each pellet that hits
+    pub damage: f64,
+    // Maximum angle (in radians) that a shot can be off from the crosshair
+    pub spread: f64,
+    // Shots per second
+    pub fire_rate: f64,
+    pub range: f64,
+    // Number of rays cast for each shot
+    pub pellets: u32,
+    pub ammo_per_shot: u32,
+}
+
+// Weapons that the player can switch between with the number keys
+pub const WEAPONS: [Weapon; 2] = [
+    Weapon {
+        name: "pistol",
+        damage: 10.0,
+        spread: 0.02,
+        fire_rate: 3.0,
+        range: 32.0,
+        pellets: 1,
+        ammo_per_shot: 1,
+    },
+    Weapon {
+        name: "shotgun",
+        damage: 6.0,
+        spread: 0.12,
+        fire_rate: 1.2,
+        range: 16.0,
+        pellets: 7,
+        ammo_per_shot: 2,
+    },
+];
+
+// Small xorshift random number generator, used for weapon spread
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new() -> Self {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Self {
+            // The state can never be 0 or it will stay 0 forever
+            state: seed | 1,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+
+    // Returns a random number between -1 and 1
+    pub fn next_signed(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64 * 2.0 - 1.0
+    }
+}
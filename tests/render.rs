@@ -0,0 +1,21 @@
+use std::process::Command;
+
+// Renders the fixture level from its spawn point with --render and checks
+// it against the golden image, if the renderer is changed on purpose then
+// save the new frame over tests/fixtures/spawn_view.bmp
+#[test]
+fn spawn_view_matches_golden_image() {
+    let output = std::env::temp_dir().join("retro-fps-spawn_view.bmp");
+    let result = Command::new(env!("CARGO_BIN_EXE_retro-fps"))
+        .args(["--render", "tests/fixtures/spawn_view.txt"])
+        .arg(&output)
+        .arg("tests/fixtures/spawn_view.bmp")
+        .output()
+        .expect("failed to run retro-fps");
+
+    assert!(
+        result.status.success(),
+        "{}",
+        String::from_utf8_lossy(&result.stderr)
+    );
+}